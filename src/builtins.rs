@@ -0,0 +1,313 @@
+use crate::env::*;
+use crate::error::*;
+use crate::eval::{apply, define_special_forms};
+use crate::object::*;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+type Builtin = fn(&[Object]) -> Result<Object, LispError>;
+
+/// The procedures bound in the base environment of every interpreter. They
+/// receive their arguments already evaluated, so they can be passed around
+/// like any other procedure, e.g. `(reduce + (list 1 2 3))`.
+const BUILTINS: &[(&str, Arity, Builtin)] = &[
+    ("+", Arity::AtLeast(0), add),
+    ("*", Arity::AtLeast(0), |args| {
+        fold("*", Object::Integer(1), args)
+    }),
+    ("-", Arity::AtLeast(1), |args| fold_or_invert("-", args)),
+    ("/", Arity::AtLeast(1), |args| fold_or_invert("/", args)),
+    ("%", Arity::Exact(2), |args| {
+        arithmetic("%", &args[0], &args[1])
+    }),
+    ("<", Arity::AtLeast(2), |args| compare_chain("<", args)),
+    (">", Arity::AtLeast(2), |args| compare_chain(">", args)),
+    ("<=", Arity::AtLeast(2), |args| compare_chain("<=", args)),
+    (">=", Arity::AtLeast(2), |args| compare_chain(">=", args)),
+    ("=", Arity::AtLeast(2), |args| compare_chain("=", args)),
+    ("!=", Arity::AtLeast(2), |args| compare_chain("!=", args)),
+    ("&", Arity::AtLeast(0), |args| logic("&", args)),
+    ("|", Arity::AtLeast(0), |args| logic("|", args)),
+    ("list", Arity::AtLeast(0), |args| {
+        Ok(Object::list(args.to_vec()))
+    }),
+    ("cons", Arity::Exact(2), |args| {
+        Ok(Object::cons(args[0].clone(), args[1].clone()))
+    }),
+    ("print", Arity::AtLeast(0), print),
+    ("write", Arity::AtLeast(0), write),
+    ("map", Arity::Exact(2), map),
+    ("filter", Arity::Exact(2), filter),
+    ("reduce", Arity::Exact(2), reduce),
+    ("range", Arity::Range(2, 3), range),
+    ("car", Arity::Exact(1), car),
+    ("cdr", Arity::Exact(1), cdr),
+    ("length", Arity::Exact(1), length),
+    ("null?", Arity::Exact(1), is_null),
+    ("pair?", Arity::Exact(1), |args| {
+        Ok(Object::Bool(matches!(args[0], Object::Pair(_))))
+    }),
+    ("set-car!", Arity::Exact(2), |args| {
+        pair_arg(&args[0])?.set_car(args[1].clone());
+        Ok(Object::Void)
+    }),
+    ("set-cdr!", Arity::Exact(2), |args| {
+        pair_arg(&args[0])?.set_cdr(args[1].clone());
+        Ok(Object::Void)
+    }),
+    ("gensym", Arity::Range(0, 1), gensym),
+];
+
+/// Returns a new global environment binding every built-in procedure and
+/// special form.
+pub fn base_env() -> Rc<RefCell<Env>> {
+    let mut env = Env::new();
+    for (name, arity, func) in BUILTINS {
+        let native = Object::NativeFn(name.to_string(), *arity, NativeFunc::new(*func));
+        env.set(name, native);
+    }
+    define_special_forms(&mut env);
+    Rc::new(RefCell::new(env))
+}
+
+fn overflow(op: &str) -> LispError {
+    LispError::limit(&format!("integer overflow in {}", op))
+}
+
+fn add(args: &[Object]) -> Result<Object, LispError> {
+    if !matches!(args.first(), Some(Object::String(_))) {
+        return fold("+", Object::Integer(0), args);
+    }
+    let mut result = String::new();
+    for arg in args {
+        match arg {
+            Object::String(s) => result.push_str(s),
+            _ => return Err(LispError::type_error("string", arg)),
+        }
+    }
+    Ok(Object::String(result))
+}
+
+fn fold(op: &str, init: Object, args: &[Object]) -> Result<Object, LispError> {
+    args.iter()
+        .try_fold(init, |acc, arg| arithmetic(op, &acc, arg))
+}
+
+/// With a single operand `-` negates it and `/` takes its reciprocal, i.e.
+/// `(- x)` is `(- 0 x)` and `(/ x)` is `(/ 1 x)`.
+fn fold_or_invert(op: &str, args: &[Object]) -> Result<Object, LispError> {
+    match args {
+        [arg] => {
+            let identity = Object::Integer(if op == "-" { 0 } else { 1 });
+            arithmetic(op, &identity, arg)
+        }
+        _ => fold(op, args[0].clone(), &args[1..]),
+    }
+}
+
+/// Applies the arithmetic operator `op` to two numbers. The result is an
+/// integer if both operands are integers, and a float otherwise.
+fn arithmetic(op: &str, left: &Object, right: &Object) -> Result<Object, LispError> {
+    if let (Object::Integer(l), Object::Integer(r)) = (left, right) {
+        let result = match op {
+            "+" => l.checked_add(*r),
+            "-" => l.checked_sub(*r),
+            "*" => l.checked_mul(*r),
+            "/" | "%" if *r == 0 => return Err(LispError::division_by_zero()),
+            "/" => l.checked_div(*r),
+            _ => l.checked_rem(*r),
+        };
+        return result.map(Object::Integer).ok_or_else(|| overflow(op));
+    }
+    let (l, r) = (as_float(left)?, as_float(right)?);
+    Ok(Object::Float(match op {
+        "+" => l + r,
+        "-" => l - r,
+        "*" => l * r,
+        "/" => l / r,
+        _ => l % r,
+    }))
+}
+
+fn as_float(obj: &Object) -> Result<f64, LispError> {
+    match obj {
+        Object::Integer(n) => Ok(*n as f64),
+        Object::Float(f) => Ok(*f),
+        _ => Err(LispError::type_error("number", obj)),
+    }
+}
+
+/// Comparisons are chained: `(< a b c)` holds when `a < b` and `b < c`.
+fn compare_chain(op: &str, args: &[Object]) -> Result<Object, LispError> {
+    let mut result = true;
+    for pair in args.windows(2) {
+        result &= compare(op, &pair[0], &pair[1])?;
+    }
+    Ok(Object::Bool(result))
+}
+
+/// Compares two numbers or two strings with the comparison operator `op`.
+/// Integers are compared exactly, and promoted to floats when compared with
+/// a float. Comparisons involving NaN are false, except for `!=`.
+fn compare(op: &str, left: &Object, right: &Object) -> Result<bool, LispError> {
+    let ordering = match (left, right) {
+        (Object::Integer(l), Object::Integer(r)) => Some(l.cmp(r)),
+        (Object::String(l), Object::String(r)) => Some(l.cmp(r)),
+        (Object::String(_), _) => return Err(LispError::type_error("string", right)),
+        _ => as_float(left)?.partial_cmp(&as_float(right)?),
+    };
+    Ok(match op {
+        "<" => ordering == Some(Ordering::Less),
+        ">" => ordering == Some(Ordering::Greater),
+        "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        ">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        "=" => ordering == Some(Ordering::Equal),
+        _ => ordering != Some(Ordering::Equal),
+    })
+}
+
+fn logic(op: &str, args: &[Object]) -> Result<Object, LispError> {
+    let mut result = op == "&";
+    for arg in args {
+        match arg {
+            Object::Bool(b) if op == "&" => result &= b,
+            Object::Bool(b) => result |= b,
+            _ => return Err(LispError::type_error("boolean", arg)),
+        }
+    }
+    Ok(Object::Bool(result))
+}
+
+fn print(args: &[Object]) -> Result<Object, LispError> {
+    for obj in args {
+        print!("{} ", obj);
+    }
+    println!();
+    Ok(Object::Void)
+}
+
+fn write(args: &[Object]) -> Result<Object, LispError> {
+    for obj in args {
+        print!("{} ", obj.written());
+    }
+    println!();
+    Ok(Object::Void)
+}
+
+fn car(args: &[Object]) -> Result<Object, LispError> {
+    Ok(pair_arg(&args[0])?.car())
+}
+
+fn cdr(args: &[Object]) -> Result<Object, LispError> {
+    Ok(pair_arg(&args[0])?.cdr())
+}
+
+fn length(args: &[Object]) -> Result<Object, LispError> {
+    match &args[0] {
+        Object::List(list) => Ok(Object::Integer(list.len() as i64)),
+        obj => Ok(Object::Integer(list_arg(obj)?.len() as i64)),
+    }
+}
+
+fn is_null(args: &[Object]) -> Result<Object, LispError> {
+    match &args[0] {
+        Object::List(list) => Ok(Object::Bool(list.is_empty())),
+        Object::Null => Ok(Object::Bool(true)),
+        Object::Pair(_) => Ok(Object::Bool(false)),
+        obj => Err(LispError::type_error("list", obj)),
+    }
+}
+
+fn range(args: &[Object]) -> Result<Object, LispError> {
+    let start = match &args[0] {
+        Object::Integer(i) => *i,
+        obj => return Err(LispError::type_error("integer", obj)),
+    };
+    let end = match &args[1] {
+        Object::Integer(i) => *i,
+        obj => return Err(LispError::type_error("integer", obj)),
+    };
+    let stride = match args.get(2) {
+        None => 1,
+        Some(Object::Integer(i)) if *i > 0 => *i,
+        Some(obj) => return Err(LispError::type_error("positive integer", obj)),
+    };
+
+    let mut new_list = Vec::new();
+    let mut next = Some(start);
+    // The range ends early if the next element would not fit in an integer,
+    // as it would be past `end` anyway.
+    while let Some(i) = next.filter(|i| *i < end) {
+        new_list.push(Object::Integer(i));
+        next = i.checked_add(stride);
+    }
+    Ok(Object::list(new_list))
+}
+
+fn map(args: &[Object]) -> Result<Object, LispError> {
+    let (func, items) = (&args[0], list_arg(&args[1])?);
+    let mut result_list = Vec::new();
+    for item in items {
+        result_list.push(apply(func, vec![item])?);
+    }
+    Ok(Object::list(result_list))
+}
+
+fn filter(args: &[Object]) -> Result<Object, LispError> {
+    let (func, items) = (&args[0], list_arg(&args[1])?);
+    let mut result_list = Vec::new();
+    for item in items {
+        match apply(func, vec![item.clone()])? {
+            Object::Bool(true) => result_list.push(item),
+            Object::Bool(false) => {}
+            result => return Err(LispError::type_error("boolean", &result)),
+        }
+    }
+    Ok(Object::list(result_list))
+}
+
+fn reduce(args: &[Object]) -> Result<Object, LispError> {
+    let func = &args[0];
+    let items = match args[1].list_items() {
+        Some(list) if list.len() >= 2 => list,
+        _ => {
+            return Err(LispError::type_error(
+                "list of at least 2 elements",
+                &args[1],
+            ))
+        }
+    };
+    let mut accumulator = items[0].clone();
+    for item in items[1..].iter() {
+        accumulator = apply(func, vec![accumulator, item.clone()])?;
+    }
+    Ok(accumulator)
+}
+
+/// Returns a fresh symbol, for macros to bind variables that cannot clash
+/// with the code they are given. Its name starts with `#:`, which the reader
+/// rejects, so no symbol in source code can be equal to it.
+fn gensym(args: &[Object]) -> Result<Object, LispError> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let prefix = match args.first() {
+        None => "g",
+        Some(Object::String(s)) => s,
+        Some(obj) => return Err(LispError::type_error("string", obj)),
+    };
+    let n = COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+    Ok(Object::Symbol(format!("#:{}{}", prefix, n)))
+}
+
+fn list_arg(obj: &Object) -> Result<Vec<Object>, LispError> {
+    obj.list_items()
+        .ok_or_else(|| LispError::type_error("proper list", obj))
+}
+
+fn pair_arg(obj: &Object) -> Result<&Pair, LispError> {
+    match obj {
+        Object::Pair(pair) => Ok(pair),
+        _ => Err(LispError::type_error("pair", obj)),
+    }
+}
@@ -0,0 +1,284 @@
+use crate::error::*;
+use crate::object::*;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+/// Conversion from a Lisp value into a Rust value.
+pub trait FromLisp: Sized {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError>;
+}
+
+/// Conversion from a Rust value into a Lisp value.
+pub trait IntoLisp {
+    fn into_lisp(self) -> Object;
+}
+
+/// Conversion from a Rust value into the argument list of a Lisp call.
+/// Implemented for tuples, where each element becomes one argument.
+pub trait IntoLispArgs {
+    fn into_lisp_args(self) -> Vec<Object>;
+}
+
+impl IntoLispArgs for () {
+    fn into_lisp_args(self) -> Vec<Object> {
+        vec![]
+    }
+}
+
+fn conversion_error(expected: &str, obj: &Object) -> LispError {
+    LispError::type_error(expected, obj)
+}
+
+impl FromLisp for Object {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        Ok(obj.clone())
+    }
+}
+
+impl IntoLisp for Object {
+    fn into_lisp(self) -> Object {
+        self
+    }
+}
+
+impl FromLisp for () {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        match obj {
+            Object::Void => Ok(()),
+            Object::Null => Ok(()),
+            _ => Err(conversion_error("void", obj)),
+        }
+    }
+}
+
+impl IntoLisp for () {
+    fn into_lisp(self) -> Object {
+        Object::Void
+    }
+}
+
+impl FromLisp for i64 {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        match obj {
+            Object::Integer(n) => Ok(*n),
+            _ => Err(conversion_error("integer", obj)),
+        }
+    }
+}
+
+impl IntoLisp for i64 {
+    fn into_lisp(self) -> Object {
+        Object::Integer(self)
+    }
+}
+
+impl FromLisp for f64 {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        match obj {
+            Object::Float(n) => Ok(*n),
+            Object::Integer(n) => Ok(*n as f64),
+            _ => Err(conversion_error("float", obj)),
+        }
+    }
+}
+
+impl IntoLisp for f64 {
+    fn into_lisp(self) -> Object {
+        Object::Float(self)
+    }
+}
+
+impl FromLisp for bool {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        match obj {
+            Object::Bool(b) => Ok(*b),
+            _ => Err(conversion_error("boolean", obj)),
+        }
+    }
+}
+
+impl IntoLisp for bool {
+    fn into_lisp(self) -> Object {
+        Object::Bool(self)
+    }
+}
+
+impl FromLisp for String {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        match obj {
+            Object::String(s) => Ok(s.clone()),
+            _ => Err(conversion_error("string", obj)),
+        }
+    }
+}
+
+impl IntoLisp for String {
+    fn into_lisp(self) -> Object {
+        Object::String(self)
+    }
+}
+
+impl IntoLisp for &str {
+    fn into_lisp(self) -> Object {
+        Object::String(self.to_string())
+    }
+}
+
+impl<T: FromLisp> FromLisp for Option<T> {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        match obj {
+            Object::Void => Ok(None),
+            _ => T::from_lisp(obj).map(Some),
+        }
+    }
+}
+
+impl<T: IntoLisp> IntoLisp for Option<T> {
+    fn into_lisp(self) -> Object {
+        match self {
+            Some(val) => val.into_lisp(),
+            None => Object::Void,
+        }
+    }
+}
+
+fn list_items(obj: &Object) -> Result<Vec<Object>, LispError> {
+    match obj {
+        Object::List(list) => Ok(list.to_vec()),
+        _ => obj
+            .list_items()
+            .ok_or_else(|| conversion_error("list", obj)),
+    }
+}
+
+impl<T: FromLisp> FromLisp for Vec<T> {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        list_items(obj)?.iter().map(T::from_lisp).collect()
+    }
+}
+
+impl<T: IntoLisp> IntoLisp for Vec<T> {
+    fn into_lisp(self) -> Object {
+        Object::list(self.into_iter().map(IntoLisp::into_lisp).collect())
+    }
+}
+
+/// Maps are represented as association lists: a list of `(key value)` lists.
+impl<T: FromLisp, S: BuildHasher + Default> FromLisp for HashMap<String, T, S> {
+    fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+        list_items(obj)?
+            .iter()
+            .map(<(String, T)>::from_lisp)
+            .collect()
+    }
+}
+
+impl<T: IntoLisp, S> IntoLisp for HashMap<String, T, S> {
+    fn into_lisp(self) -> Object {
+        Object::list(
+            self.into_iter()
+                .map(|(k, v)| Object::list(vec![Object::String(k), v.into_lisp()]))
+                .collect(),
+        )
+    }
+}
+
+macro_rules! impl_tuple {
+    ($len:expr, $($name:ident : $idx:tt),+) => {
+        impl<$($name: FromLisp),+> FromLisp for ($($name,)+) {
+            fn from_lisp(obj: &Object) -> Result<Self, LispError> {
+                let items = list_items(obj)?;
+                if items.len() != $len {
+                    return Err(conversion_error(
+                        concat!("list of ", stringify!($len), " elements"),
+                        obj,
+                    ));
+                }
+                Ok(($($name::from_lisp(&items[$idx])?,)+))
+            }
+        }
+
+        impl<$($name: IntoLisp),+> IntoLisp for ($($name,)+) {
+            fn into_lisp(self) -> Object {
+                Object::list(vec![$(self.$idx.into_lisp()),+])
+            }
+        }
+
+        impl<$($name: IntoLisp),+> IntoLispArgs for ($($name,)+) {
+            fn into_lisp_args(self) -> Vec<Object> {
+                vec![$(self.$idx.into_lisp()),+]
+            }
+        }
+    };
+}
+
+impl_tuple!(1, A: 0);
+impl_tuple!(2, A: 0, B: 1);
+impl_tuple!(3, A: 0, B: 1, C: 2);
+impl_tuple!(4, A: 0, B: 1, C: 2, D: 3);
+impl_tuple!(5, A: 0, B: 1, C: 2, D: 3, E: 4);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scalars() {
+        assert_eq!(i64::from_lisp(&Object::Integer(7)), Ok(7));
+        assert_eq!(f64::from_lisp(&Object::Integer(7)), Ok(7.0));
+        assert_eq!(bool::from_lisp(&Object::Bool(true)), Ok(true));
+        assert_eq!(
+            String::from_lisp(&Object::String("abc".to_string())),
+            Ok("abc".to_string())
+        );
+        assert_eq!("abc".into_lisp(), Object::String("abc".to_string()));
+        assert_eq!(2.5.into_lisp(), Object::Float(2.5));
+    }
+
+    #[test]
+    fn test_conversion_error() {
+        let err = i64::from_lisp(&Object::String("abc".to_string())).unwrap_err();
+        assert_eq!(
+            err,
+            LispError::Type {
+                expected: "integer".to_string(),
+                found: Object::String("abc".to_string()),
+                span: None,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Type error: expected integer, found string abc"
+        );
+    }
+
+    #[test]
+    fn test_option() {
+        assert_eq!(Option::<i64>::from_lisp(&Object::Void), Ok(None));
+        assert_eq!(Option::<i64>::from_lisp(&Object::Integer(1)), Ok(Some(1)));
+        assert_eq!(None::<i64>.into_lisp(), Object::Void);
+    }
+
+    #[test]
+    fn test_vec_and_tuple() {
+        let obj = vec![(1, "one"), (2, "two")].into_lisp();
+        assert_eq!(
+            obj,
+            Object::list(vec![
+                Object::list(vec![Object::Integer(1), Object::String("one".to_string())]),
+                Object::list(vec![Object::Integer(2), Object::String("two".to_string())]),
+            ])
+        );
+        let back = Vec::<(i64, String)>::from_lisp(&obj).unwrap();
+        assert_eq!(back, vec![(1, "one".to_string()), (2, "two".to_string())]);
+        assert!(<(i64, i64, i64)>::from_lisp(&obj).is_err());
+    }
+
+    #[test]
+    fn test_hash_map() {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), 1);
+        let obj = map.clone().into_lisp();
+        assert_eq!(HashMap::<String, i64>::from_lisp(&obj), Ok(map));
+    }
+}
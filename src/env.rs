@@ -1,3 +1,4 @@
+use crate::error::LispError;
 use crate::object::Object;
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -43,4 +44,36 @@ impl Env {
     pub fn set(&mut self, name: &str, val: Object) {
         self.vars.insert(name.to_string(), val);
     }
+
+    /// Changes the value of the existing binding of `name`, in this scope or
+    /// in the nearest parent scope binding it.
+    pub fn assign(&mut self, name: &str, val: Object) -> Result<(), LispError> {
+        if let Some(slot) = self.vars.get_mut(name) {
+            *slot = val;
+            return Ok(());
+        }
+        match &self.parent {
+            Some(parent) => parent.borrow_mut().assign(name, val),
+            None => Err(LispError::unbound(name)),
+        }
+    }
+
+    /// Returns the scope binding `name`: `env` or its nearest parent scope
+    /// binding it.
+    pub fn scope_of(env: &Rc<RefCell<Self>>, name: &str) -> Option<Rc<RefCell<Self>>> {
+        let mut scope = env.clone();
+        loop {
+            if scope.borrow().vars.contains_key(name) {
+                return Some(scope);
+            }
+            let parent = scope.borrow().parent.clone()?;
+            scope = parent;
+        }
+    }
+
+    /// Removes the binding of `name` from this scope, leaving the bindings
+    /// of parent scopes untouched.
+    pub fn remove(&mut self, name: &str) -> Option<Object> {
+        self.vars.remove(name)
+    }
 }
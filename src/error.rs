@@ -0,0 +1,197 @@
+use crate::lexer::TokenError;
+use crate::object::*;
+use crate::parser::ParseError;
+use crate::span::Span;
+use std::error::Error;
+use std::fmt;
+
+/// Every error the lexer, parser and evaluator can produce. Errors raised
+/// while evaluating carry the location of the innermost form being evaluated
+/// when they occurred, if it is known.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LispError {
+    Tokenize(TokenError),
+    Parse(ParseError),
+    UnboundSymbol {
+        name: String,
+        span: Option<Box<Span>>,
+    },
+    Type {
+        expected: String,
+        found: Object,
+        span: Option<Box<Span>>,
+    },
+    Arity {
+        name: String,
+        expected: Arity,
+        found: usize,
+        span: Option<Box<Span>>,
+    },
+    DivisionByZero {
+        span: Option<Box<Span>>,
+    },
+    /// A special form used with the wrong shape, e.g. `(define)`.
+    Syntax {
+        message: String,
+        form: Object,
+        span: Option<Box<Span>>,
+    },
+    /// An error raised by a native function.
+    User {
+        message: String,
+        span: Option<Box<Span>>,
+    },
+    LimitExceeded {
+        limit: String,
+        span: Option<Box<Span>>,
+    },
+}
+
+impl LispError {
+    pub fn unbound(name: &str) -> Self {
+        LispError::UnboundSymbol {
+            name: name.to_string(),
+            span: None,
+        }
+    }
+
+    pub fn type_error(expected: &str, found: &Object) -> Self {
+        LispError::Type {
+            expected: expected.to_string(),
+            found: found.clone(),
+            span: None,
+        }
+    }
+
+    pub fn arity(name: &str, expected: Arity, found: usize) -> Self {
+        LispError::Arity {
+            name: name.to_string(),
+            expected,
+            found,
+            span: None,
+        }
+    }
+
+    pub fn division_by_zero() -> Self {
+        LispError::DivisionByZero { span: None }
+    }
+
+    pub fn syntax(message: &str, form: &Object) -> Self {
+        LispError::Syntax {
+            message: message.to_string(),
+            form: form.clone(),
+            span: None,
+        }
+    }
+
+    pub fn user(message: &str) -> Self {
+        LispError::User {
+            message: message.to_string(),
+            span: None,
+        }
+    }
+
+    pub fn limit(limit: &str) -> Self {
+        LispError::LimitExceeded {
+            limit: limit.to_string(),
+            span: None,
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            LispError::Tokenize(e) => Some(e.span()),
+            LispError::Parse(e) => e.span(),
+            LispError::UnboundSymbol { span, .. }
+            | LispError::Type { span, .. }
+            | LispError::Arity { span, .. }
+            | LispError::DivisionByZero { span }
+            | LispError::Syntax { span, .. }
+            | LispError::User { span, .. }
+            | LispError::LimitExceeded { span, .. } => span.as_deref(),
+        }
+    }
+
+    /// Sets the location of an evaluation error.
+    pub fn with_span(mut self, new_span: Span) -> Self {
+        match &mut self {
+            LispError::Tokenize(_) | LispError::Parse(_) => {}
+            LispError::UnboundSymbol { span, .. }
+            | LispError::Type { span, .. }
+            | LispError::Arity { span, .. }
+            | LispError::DivisionByZero { span }
+            | LispError::Syntax { span, .. }
+            | LispError::User { span, .. }
+            | LispError::LimitExceeded { span, .. } => *span = Some(Box::new(new_span)),
+        }
+        self
+    }
+}
+
+impl fmt::Display for LispError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LispError::Tokenize(e) => return write!(f, "{}", e),
+            LispError::Parse(e) => return write!(f, "{}", e),
+            LispError::UnboundSymbol { name, .. } => write!(f, "Unbound symbol: {}", name)?,
+            LispError::Type {
+                expected, found, ..
+            } => write!(
+                f,
+                "Type error: expected {}, found {} {}",
+                expected,
+                found.type_name(),
+                found
+            )?,
+            LispError::Arity {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "Arity error: {} expects {} argument(s), got {}",
+                name, expected, found
+            )?,
+            LispError::DivisionByZero { .. } => write!(f, "Division by zero")?,
+            LispError::Syntax { message, form, .. } => {
+                write!(f, "Syntax error: {} in {}", message, form)?
+            }
+            LispError::User { message, .. } => write!(f, "Error: {}", message)?,
+            LispError::LimitExceeded { limit, .. } => write!(f, "Limit exceeded: {}", limit)?,
+        }
+        if let Some(span) = self.span() {
+            write!(f, "\n{}", span)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for LispError {}
+
+impl From<TokenError> for LispError {
+    fn from(e: TokenError) -> Self {
+        LispError::Tokenize(e)
+    }
+}
+
+impl From<ParseError> for LispError {
+    fn from(e: ParseError) -> Self {
+        LispError::Parse(e)
+    }
+}
+
+impl From<String> for LispError {
+    fn from(message: String) -> Self {
+        LispError::User {
+            message,
+            span: None,
+        }
+    }
+}
+
+impl From<&str> for LispError {
+    fn from(message: &str) -> Self {
+        LispError::user(message)
+    }
+}
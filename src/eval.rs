@@ -1,976 +1,2812 @@
 use crate::env::*;
+use crate::error::*;
 use crate::object::*;
 use crate::parser::*;
+use crate::span::*;
+use crate::syntax_rules::*;
 use std::cell::RefCell;
-use std::cmp::Ordering;
 use std::rc::Rc;
 
-fn print_list(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let mut new_list = Vec::new();
+fn syntax_error(message: &str, list: &[Object]) -> LispError {
+    LispError::syntax(message, &Object::List(Rc::new(list.to_vec())))
+}
 
-    for obj in list[1..].iter() {
-        new_list.push(eval_obj(obj, env)?);
-    }
-    for obj in new_list.iter() {
-        print!("{} ", obj);
+fn check_arity(list: &[Object], expected: Arity) -> Result<(), LispError> {
+    if !expected.accepts(list.len() - 1) {
+        return Err(LispError::arity(
+            &list[0].to_string(),
+            expected,
+            list.len() - 1,
+        ));
     }
-    println!();
-    Ok(Object::Void)
+    Ok(())
 }
 
-fn eval_car(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let l = eval_obj(&list[1], env)?;
-    match l {
-        Object::ListData(list) => Ok(list[0].clone()),
-        _ => Err(format!("{} is not a list", l)),
-    }
+/// `(begin body...)` evaluates `body` in a new scope. The last form is in
+/// tail position.
+fn eval_begin(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+    eval_body(&list[1..], &mut new_env)
 }
 
-fn eval_cdr(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let l = eval_obj(&list[1], env)?;
-    let mut new_list = vec![];
-    match l {
-        Object::ListData(list) => {
-            for obj in list[1..].iter() {
-                new_list.push(obj.clone());
-            }
-            Ok(Object::ListData(new_list))
+/// Parses the `((name init)...)` bindings of a `let`-like form.
+fn let_bindings<'a>(
+    bindings: &'a Object,
+    list: &[Object],
+) -> Result<Vec<(&'a String, &'a Object)>, LispError> {
+    let invalid = || syntax_error(&format!("Invalid binding for {}", list[0]), list);
+    let bindings = match bindings {
+        Object::List(bindings) => bindings,
+        _ => {
+            return Err(syntax_error(
+                &format!("Invalid bindings for {}", list[0]),
+                list,
+            ))
         }
-        _ => Err(format!("{} is not a list", l)),
-    }
+    };
+    bindings
+        .iter()
+        .map(|binding| match binding {
+            Object::List(binding) => match binding.as_slice() {
+                [Object::Symbol(name), init] => Ok((name, init)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        })
+        .collect()
 }
 
-fn eval_length(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let obj = eval_obj(&list[1], env)?;
-    match obj {
-        Object::List(list) => Ok(Object::Integer(list.len() as i64)),
-        Object::ListData(list) => Ok(Object::Integer(list.len() as i64)),
-        _ => Err(format!("{} is not a list", obj)),
+/// `(let ((name init)...) body...)` evaluates the inits in the current
+/// environment and `body` with the names bound to their values.
+/// `(let loop ((name init)...) body...)` also binds `loop` to a procedure
+/// taking the names as parameters and evaluating `body`, to loop by calling
+/// it in tail position.
+fn eval_let(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    if list.len() < 3 {
+        return Err(syntax_error("Invalid number of arguments for let", list));
     }
-}
-
-fn eval_is_null(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let obj = eval_obj(&list[1], env)?;
-    match obj {
-        Object::List(list) => Ok(Object::Bool(list.is_empty())),
-        Object::ListData(list) => Ok(Object::Bool(list.is_empty())),
-        _ => Err(format!("{} is not a list", obj)),
+    if let Object::Symbol(name) = &list[1] {
+        return eval_named_let(name, list, env);
     }
-}
 
-fn eval_binary_op(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    if list.len() != 3 {
-        return Err("Invalid number of arguments for infix operator".to_string());
-    }
-    let operator = list[0].clone();
-    let left = &eval_obj(&list[1].clone(), env)?;
-    let right = &eval_obj(&list[2].clone(), env)?;
-    match operator {
-        Object::BinaryOp(s) => match s.as_str() {
-            "+" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l + r)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l + r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 + r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l + *r as f64)),
-                (Object::String(l), Object::String(r)) => Ok(Object::String(l.to_owned() + r)),
-                _ => Err(format!("Invalid types for + operator {} {}", left, right)),
-            },
-            "-" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l - r)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l - r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 - r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l - *r as f64)),
-                _ => Err(format!("Invalid types for - operator {} {}", left, right)),
-            },
-            "*" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l * r)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l * r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 * r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l * (*r) as f64)),
-                _ => Err(format!("Invalid types for * operator {} {}", left, right)),
-            },
-            "/" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l / r)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l / r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 / r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l / (*r) as f64)),
-                _ => Err(format!("Invalid types for / operator {} {}", left, right)),
-            },
-            "%" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Integer(l % r)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Float(l % r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Float(*l as f64 % r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Float(l % (*r) as f64)),
-                _ => Err(format!("Invalid types for % operator {} {}", left, right)),
-            },
-            "<" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Bool(l < r)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Bool(l < r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Bool((*l as f64) < *r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Bool(l < &(*r as f64))),
-                (Object::String(l), Object::String(r)) => {
-                    Ok(Object::Bool(l.cmp(r) == Ordering::Less))
-                }
-                _ => Err(format!("Invalid types for < operator {} {}", left, right)),
-            },
-            ">" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Bool(l > r)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Bool(l > r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Bool(*l as f64 > *r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Bool(l > &(*r as f64))),
-                (Object::String(l), Object::String(r)) => {
-                    Ok(Object::Bool(l.cmp(r) == Ordering::Greater))
-                }
-                _ => Err(format!("Invalid types for > operator {} {}", left, right)),
-            },
-            "=" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Bool(l == r)),
-                (Object::String(l), Object::String(r)) => Ok(Object::Bool(l == r)),
-                _ => Err(format!("Invalid types for = operator {} {}", left, right)),
-            },
-            "!=" => match (left, right) {
-                (Object::Integer(l), Object::Integer(r)) => Ok(Object::Bool(l != r)),
-                (Object::Float(l), Object::Float(r)) => Ok(Object::Bool(l != r)),
-                (Object::Integer(l), Object::Float(r)) => Ok(Object::Bool(*l as f64 != *r)),
-                (Object::Float(l), Object::Integer(r)) => Ok(Object::Bool(*l != (*r) as f64)),
-                (Object::String(l), Object::String(r)) => {
-                    Ok(Object::Bool(l.cmp(r) != Ordering::Equal))
-                }
-                _ => Err(format!("Invalid types for != operator {} {}", left, right)),
-            },
-            "&" => match (left, right) {
-                (Object::Bool(l), Object::Bool(r)) => Ok(Object::Bool(*l && *r)),
-                _ => Err(format!("Invalid types for & operator {} {}", left, right)),
-            },
-            "|" => match (left, right) {
-                (Object::Bool(l), Object::Bool(r)) => Ok(Object::Bool(*l || *r)),
-                _ => Err(format!("Invalid types for | operator {} {}", left, right)),
-            },
-            _ => Err(format!("Invalid infix operator: {}", s)),
-        },
-        _ => Err("Operator must be a symbol".to_string()),
+    let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+    for (name, init) in let_bindings(&list[1], list)? {
+        let value = eval_obj(init, env)?;
+        new_env.borrow_mut().set(name, value);
     }
+    eval_body(&list[2..], &mut new_env)
 }
 
-fn eval_begin(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let mut result = Object::Void;
-    let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+fn eval_named_let(
+    name: &str,
+    list: &[Object],
+    env: &mut Rc<RefCell<Env>>,
+) -> Result<Step, LispError> {
+    if list.len() < 4 {
+        return Err(syntax_error("Invalid number of arguments for let", list));
+    }
+    let bindings = let_bindings(&list[2], list)?;
+    let mut args = Vec::with_capacity(bindings.len());
+    for (_, init) in bindings.iter() {
+        args.push(eval_obj(init, env)?);
+    }
+
+    let loop_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+    let params = bindings
+        .iter()
+        .map(|(name, _)| Object::Symbol(name.to_string()));
+    let params = Object::List(Rc::new(params.collect()));
+    let lambda = make_lambda(Some(name), &params, &list[3..], list, &loop_env)?;
+    loop_env.borrow_mut().set(name, lambda.clone());
+    Ok(Step::Eval(
+        Object::List(Rc::new(
+            std::iter::once(lambda)
+                .chain(args.into_iter().map(value_form))
+                .collect(),
+        )),
+        loop_env,
+    ))
+}
 
-    for obj in list[1..].iter() {
-        result = eval_obj(obj, &mut new_env)?;
+/// `(let* ((name init)...) body...)` is like `let`, but evaluates every init
+/// with the names before it bound.
+fn eval_let_star(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    if list.len() < 3 {
+        return Err(syntax_error("Invalid number of arguments for let*", list));
     }
-    Ok(result)
+    let mut new_env = env.clone();
+    for (name, init) in let_bindings(&list[1], list)? {
+        let value = eval_obj(init, &mut new_env)?;
+        new_env = Rc::new(RefCell::new(Env::extend(new_env)));
+        new_env.borrow_mut().set(name, value);
+    }
+    let mut body_env = Rc::new(RefCell::new(Env::extend(new_env)));
+    eval_body(&list[2..], &mut body_env)
 }
 
-fn eval_let(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let mut result = Object::Void;
-    let bindings_env = Rc::new(RefCell::new(Env::new()));
-
+/// `(letrec ((name init)...) body...)` evaluates the inits in order in the
+/// environment binding all the names, so that procedures defined by the
+/// inits can refer to each other. `letrec*` is the same form.
+fn eval_letrec(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
     if list.len() < 3 {
-        return Err("Invalid number of arguments for let".to_string());
+        return Err(syntax_error(
+            &format!("Invalid number of arguments for {}", list[0]),
+            list,
+        ));
+    }
+    let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+    for (name, init) in let_bindings(&list[1], list)? {
+        let value = eval_obj(init, &mut new_env)?;
+        new_env.borrow_mut().set(name, value);
     }
+    eval_body(&list[2..], &mut new_env)
+}
 
-    let bindings = match list[1].clone() {
-        Object::List(bindings) => bindings,
-        _ => return Err("Invalid bindings for let".to_string()),
+/// `(do ((name init step)...) (test result...) body...)` binds the names to
+/// the inits, then evaluates `body` and rebinds the names to their steps
+/// until `test` is true, and evaluates to the results. A name without a step
+/// keeps its value.
+fn eval_do(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let invalid = || syntax_error("Invalid do", list);
+    let (specs, exit) = match list {
+        [_, Object::List(specs), Object::List(exit), ..] if !exit.is_empty() => (specs, exit),
+        _ => return Err(invalid()),
     };
-
-    for binding in bindings.iter() {
-        let binding = match binding {
-            Object::List(binding) => binding,
-            _ => return Err("Invalid binding for let".to_string()),
-        };
-
-        if binding.len() != 2 {
-            return Err("Invalid binding for let".to_string());
+    let mut vars = Vec::with_capacity(specs.len());
+    for spec in specs.iter() {
+        match spec {
+            Object::List(spec) => match spec.as_slice() {
+                [Object::Symbol(name), init] => vars.push((name, init, None)),
+                [Object::Symbol(name), init, step] => vars.push((name, init, Some(step))),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
         }
-
-        let name = match binding[0].clone() {
-            Object::Symbol(name) => name,
-            _ => return Err("Invalid binding for let".to_string()),
-        };
-
-        let value = eval_obj(&binding[1], env)?;
-        bindings_env.borrow_mut().set(name.as_str(), value);
     }
 
-    println!("let arguments {:?}", bindings_env);
-    let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
-    new_env.borrow_mut().update(bindings_env);
-
-    for obj in list[2..].iter() {
-        result = eval_obj(obj, &mut new_env)?;
+    let mut loop_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+    for (name, init, _) in vars.iter() {
+        let value = eval_obj(init, env)?;
+        loop_env.borrow_mut().set(name, value);
+    }
+    loop {
+        if is_true(&eval_obj(&exit[0], &mut loop_env)?) {
+            return eval_body(&exit[1..], &mut loop_env);
+        }
+        for obj in list[3..].iter() {
+            eval_obj(obj, &mut loop_env)?;
+        }
+        // Every iteration has its own bindings, so that closures created by
+        // the body keep the values they saw.
+        let next_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+        for (name, _, step) in vars.iter() {
+            let value = match step {
+                Some(step) => eval_obj(step, &mut loop_env)?,
+                None => eval_symbol(name, &mut loop_env)?,
+            };
+            next_env.borrow_mut().set(name, value);
+        }
+        loop_env = next_env;
     }
-    Ok(result)
 }
 
-fn eval_define(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    if list.len() != 3 {
-        return Err("Invalid number of arguments for define".to_string());
+/// `(define name value)` binds `name` in the current scope, and
+/// `(define (name params...) body...)` binds it to a lambda.
+fn eval_define(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    if list.len() < 3 || (list.len() > 3 && !matches!(list[1], Object::List(_))) {
+        return Err(syntax_error("Invalid number of arguments for define", list));
     }
 
     let sym = match &list[1] {
         Object::Symbol(s) => s.clone(),
         Object::List(l) => {
-            let name = match &l[0] {
-                Object::Symbol(s) => s.clone(),
-                _ => return Err("Invalid symbol for define".to_string()),
+            let name = match l.first() {
+                Some(Object::Symbol(s)) => s.clone(),
+                _ => return Err(syntax_error("Invalid symbol for define", list)),
             };
             let params = Object::List(Rc::new(l[1..].to_vec()));
-            let body = list[2].clone();
-            let lambda = eval_function_definition(&[Object::Void, params, body], env)?;
+            let lambda = make_lambda(Some(&name), &params, &list[2..], list, env)?;
             env.borrow_mut().set(&name, lambda);
             return Ok(Object::Void);
         }
-        _ => return Err("Invalid define".to_string()),
+        _ => return Err(syntax_error("Invalid define", list)),
+    };
+    let val = match eval_obj(&list[2], env)? {
+        // Anonymous lambdas are named after the variable they are bound to.
+        Object::Lambda(params, body, func_env) if params.name.is_none() => {
+            let params = Params {
+                name: Some(sym.clone()),
+                ..(*params).clone()
+            };
+            Object::Lambda(Rc::new(params), body, func_env)
+        }
+        val => val,
     };
-    let val = eval_obj(&list[2], env)?;
     env.borrow_mut().set(&sym, val);
     Ok(Object::Void)
 }
 
-fn eval_list_data(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let mut new_list = Vec::new();
+/// `(set! name value)` changes the value of the variable `name` where it is
+/// bound, so that closures sharing the binding see the new value.
+fn eval_set(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let name = match list {
+        [_, Object::Symbol(name), _] => name,
+        _ => return Err(syntax_error("Invalid set!", list)),
+    };
+    let val = eval_obj(&list[2], env)?;
+    match resolve_alias(name) {
+        Some((name, def_env)) => def_env.borrow_mut().assign(&name, val)?,
+        None => env.borrow_mut().assign(name, val)?,
+    }
+    Ok(Object::Void)
+}
 
-    for obj in list[1..].iter() {
-        new_list.push(eval_obj(obj, env)?);
+/// `(define-macro (name params...) body...)` binds `name` to a macro. A call
+/// to the macro evaluates `body` with the parameters bound to the unevaluated
+/// arguments of the call, as data, and evaluates the resulting form in place
+/// of the call. Each call is only expanded the first time it is evaluated.
+fn eval_define_macro(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let signature = match list {
+        [_, Object::List(signature), _, ..] => signature,
+        _ => return Err(syntax_error("Invalid define-macro", list)),
+    };
+    let name = match signature.first() {
+        Some(Object::Symbol(s)) => s.clone(),
+        _ => return Err(syntax_error("Invalid macro name for define-macro", list)),
+    };
+    let params = Object::List(Rc::new(signature[1..].to_vec()));
+    let lambda = make_lambda(Some(&name), &params, &list[2..], list, env)?;
+    if let Object::Lambda(params, body, macro_env) = lambda {
+        env.borrow_mut()
+            .set(&name, Object::Macro(params, body, macro_env));
     }
-    Ok(Object::ListData(new_list))
+    Ok(Object::Void)
 }
 
-fn eval_range(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    if list.len() != 3 && list.len() != 4 {
-        return Err("Invalid number of arguments for range".to_string());
+/// Converts data back into code, the inverse of `quote_datum`.
+fn datum_to_code(obj: Object) -> Object {
+    let mut items = Vec::new();
+    let mut rest = obj;
+    loop {
+        match rest {
+            Object::Pair(pair) => {
+                items.push(datum_to_code(pair.car()));
+                rest = pair.cdr();
+            }
+            Object::Null => return Object::List(Rc::new(items)),
+            // An improper list is written `(a b . c)`.
+            tail if !items.is_empty() => {
+                items.push(Object::Symbol(".".to_string()));
+                items.push(datum_to_code(tail));
+                return Object::List(Rc::new(items));
+            }
+            obj => return obj,
+        }
     }
+}
 
-    let start = eval_obj(&list[1], env)?;
-    let end = eval_obj(&list[2], env)?;
-    let mut stride = 1;
-    if list.len() == 4 {
-        let stride_obj = eval_obj(&list[3], env)?;
-        stride = match stride_obj {
-            Object::Integer(i) => i,
-            _ => return Err("Invalid stride for range".to_string()),
-        };
+/// Splits the code of a list into its elements and, for a dotted list
+/// `(a b . c)`, its tail `c`.
+fn split_dotted(list: &[Object]) -> (&[Object], Option<&Object>) {
+    match list {
+        [items @ .., Object::Symbol(dot), tail] if dot == "." => (items, Some(tail)),
+        _ => (list, None),
     }
+}
+
+/// Expands a call to the macro `(params, body, macro_env)`, returning the
+/// form that replaces `list`.
+fn expand_macro(
+    params: &Params,
+    body: &Rc<Vec<Object>>,
+    macro_env: &Rc<RefCell<Env>>,
+    list: &Rc<Vec<Object>>,
+) -> Result<Object, LispError> {
+    let args = list[1..].iter().map(quote_datum).collect();
+    let mut new_env = bind_params(params, args, macro_env)?;
+    let expansion = datum_to_code(eval_sequence(body, &mut new_env)?);
+    // Errors in the expansion are reported at the macro call.
+    if let (Object::List(expanded), Some(span)) = (&expansion, span_of(list)) {
+        record_span(expanded, span);
+    }
+    Ok(expansion)
+}
 
-    let start = match start {
-        Object::Integer(i) => i,
-        _ => return Err("Invalid start for range".to_string()),
+/// Expands `form`, given as data, once if it is a macro call. Returns the
+/// expansion and whether `form` was a macro call.
+fn macroexpand_1(form: &Object, env: &Rc<RefCell<Env>>) -> Result<(Object, bool), LispError> {
+    let list = match datum_to_code(form.clone()) {
+        Object::List(list) => list,
+        _ => return Ok((form.clone(), false)),
     };
-    let end = match end {
-        Object::Integer(i) => i,
-        _ => return Err("Invalid end for range".to_string()),
+    let head = match list.first() {
+        Some(Object::Symbol(s)) => lookup(s, env),
+        _ => None,
     };
+    match head {
+        Some(Object::Macro(params, body, macro_env)) => {
+            let expansion = expand_macro(&params, &body, &macro_env, &list)?;
+            Ok((quote_datum(&expansion), true))
+        }
+        Some(Object::SyntaxRules(rules)) => {
+            let expansion = expand_syntax_rules(&rules, &list, env)?;
+            Ok((quote_datum(&expansion), true))
+        }
+        _ => Ok((form.clone(), false)),
+    }
+}
+
+/// Expands a call to a `syntax-rules` macro, returning the form that
+/// replaces `list`.
+fn expand_syntax_rules(
+    rules: &SyntaxRules,
+    list: &Rc<Vec<Object>>,
+    env: &Rc<RefCell<Env>>,
+) -> Result<Object, LispError> {
+    let expansion = rules.expand(list, env)?;
+    if let (Object::List(expanded), Some(span)) = (&expansion, span_of(list)) {
+        record_span(expanded, span);
+    }
+    Ok(expansion)
+}
+
+// Calls to `define-macro` macros are expanded only the first time they are
+// evaluated, which assumes the transformer has no side effects: one reading
+// or changing global variables runs once per call site, not once per call.
+// `syntax-rules` macros are not cached, as their expansion also depends on
+// the environment of the call. Like spans, expansions are kept in a side
+// table keyed by the address of the call, along with the macro that made
+// them, so that redefining the macro expands the call again.
+thread_local! {
+    static EXPANSIONS: RefCell<WeakTable<Vec<Object>, (Object, Object)>> =
+        RefCell::new(WeakTable::new());
+}
+
+/// Returns the expansion of the call `list` to `macro_obj`, calling `expand`
+/// to make it unless the call has been expanded by the same macro before.
+fn expand_once(
+    list: &Rc<Vec<Object>>,
+    macro_obj: &Object,
+    expand: impl FnOnce() -> Result<Object, LispError>,
+) -> Result<Object, LispError> {
+    let key = Rc::as_ptr(list) as usize;
+    let cached = EXPANSIONS.with(|cache| {
+        let cache = cache.borrow();
+        let (call, (expanded_by, expansion)) = cache.get(key)?;
+        let same_call = Rc::ptr_eq(&call, list);
+        (same_call && same_macro(expanded_by, macro_obj)).then(|| expansion.clone())
+    });
+    if let Some(expansion) = cached {
+        return Ok(expansion);
+    }
+    let expansion = expand()?;
+    EXPANSIONS.with(|cache| {
+        let entry = (macro_obj.clone(), expansion.clone());
+        cache.borrow_mut().insert(key, list, entry);
+    });
+    Ok(expansion)
+}
+
+fn same_macro(a: &Object, b: &Object) -> bool {
+    match (a, b) {
+        (Object::Macro(p1, b1, e1), Object::Macro(p2, b2, e2)) => {
+            Rc::ptr_eq(p1, p2) && Rc::ptr_eq(b1, b2) && Rc::ptr_eq(e1, e2)
+        }
+        _ => false,
+    }
+}
+
+fn eval_syntax_rules(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let rules = SyntaxRules::new(list, env)?;
+    Ok(Object::SyntaxRules(Rc::new(rules)))
+}
 
-    let mut new_list = Vec::new();
-    let mut i = start;
-    while i < end {
-        new_list.push(Object::Integer(i));
-        i += stride;
+/// Evaluates the transformer of `define-syntax` or of a `let-syntax`
+/// binding, which must be a `syntax-rules` macro.
+fn eval_transformer(obj: &Object, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    match eval_obj(obj, env)? {
+        rules @ Object::SyntaxRules(_) => Ok(rules),
+        other => Err(LispError::type_error("syntax-rules macro", &other)),
     }
+}
 
-    Ok(Object::ListData(new_list))
+/// `(define-syntax name (syntax-rules ...))` binds `name` to a macro.
+fn eval_define_syntax(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let name = match list {
+        [_, Object::Symbol(name), _] => name,
+        _ => return Err(syntax_error("Invalid define-syntax", list)),
+    };
+    let rules = eval_transformer(&list[2], env)?;
+    env.borrow_mut().set(name, rules);
+    Ok(Object::Void)
 }
 
-fn eval_function_definition(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let params = match &list[1] {
-        Object::List(list) => {
-            let mut params = Vec::new();
-            for param in (*list).iter() {
-                match param {
-                    Object::Symbol(s) => params.push(s.clone()),
-                    _ => return Err(format!("Invalid lambda parameter {:?}", param)),
+/// `(let-syntax ((name (syntax-rules ...))...) body...)` evaluates `body`
+/// with the macros bound in a new scope. The last form of `body` is in tail
+/// position.
+fn eval_let_syntax(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let bindings = match list.get(1) {
+        Some(Object::List(bindings)) if list.len() > 2 => bindings,
+        _ => return Err(syntax_error("Invalid let-syntax", list)),
+    };
+    let mut new_env = Rc::new(RefCell::new(Env::extend(env.clone())));
+    for binding in bindings.iter() {
+        match binding {
+            Object::List(binding) => match binding.as_slice() {
+                [Object::Symbol(name), transformer] => {
+                    let rules = eval_transformer(transformer, env)?;
+                    new_env.borrow_mut().set(name, rules);
                 }
-            }
-            params
+                _ => return Err(syntax_error("Invalid binding for let-syntax", list)),
+            },
+            _ => return Err(syntax_error("Invalid binding for let-syntax", list)),
         }
-        _ => return Err("Invalid lambda".to_string()),
-    };
+    }
+    eval_body(&list[2..], &mut new_env)
+}
+
+/// Evaluates a sequence of forms in `env` and returns the value of the last
+/// one.
+fn eval_sequence(body: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    match eval_body(body, env)? {
+        Step::Value(value) => Ok(value),
+        Step::Eval(obj, mut env) => eval_obj(&obj, &mut env),
+    }
+}
 
-    let body = match &list[2] {
-        Object::List(list) => list.clone(),
-        _ => return Err("Invalid lambda".to_string()),
+/// Evaluates a sequence of forms in `env`, leaving the last one to evaluate
+/// in tail position. An empty sequence evaluates to void.
+fn eval_body(body: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let (last, init) = match body.split_last() {
+        Some(split) => split,
+        None => return Ok(Step::Value(Object::Void)),
     };
-    Ok(Object::Lambda(params, Rc::new(body.to_vec()), env.clone()))
+    for obj in init {
+        eval_obj(obj, env)?;
+    }
+    Ok(Step::Eval(last.clone(), env.clone()))
+}
+
+/// `(macroexpand-1 form)` expands the macro call `form` once, and
+/// `(macroexpand form)` until it is no longer a macro call.
+fn eval_macroexpand(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    check_arity(list, Arity::Exact(1))?;
+    let mut form = eval_obj(&list[1], env)?;
+    loop {
+        let (expansion, expanded) = macroexpand_1(&form, env)?;
+        form = expansion;
+        if !expanded || matches!(&list[0], Object::Symbol(s) if source_name(s) == "macroexpand-1") {
+            return Ok(form);
+        }
+    }
 }
 
-fn eval_map(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    if list.len() != 3 {
-        return Err(format!("Invalid number of arguments for map {:?}", list));
+/// `(lambda params body...)`
+fn eval_function_definition(
+    list: &[Object],
+    env: &mut Rc<RefCell<Env>>,
+) -> Result<Object, LispError> {
+    if list.len() < 3 {
+        return Err(syntax_error("Invalid lambda", list));
     }
+    make_lambda(None, &list[1], &list[2..], list, env)
+}
 
-    let lambda = eval_obj(&list[1], env)?;
-    let arg_list = eval_obj(&list[2], env)?;
+/// Builds a lambda closing over `env`, named `name` if it is defined with a
+/// name. Its body is a sequence of forms, evaluated in order by every call,
+/// in a new scope holding the parameters and the internal definitions of the
+/// body.
+fn make_lambda(
+    name: Option<&str>,
+    params: &Object,
+    body: &[Object],
+    list: &[Object],
+    env: &Rc<RefCell<Env>>,
+) -> Result<Object, LispError> {
+    let params = Params {
+        name: name.map(str::to_string),
+        ..parse_params(params, list)?
+    };
+    Ok(Object::Lambda(
+        Rc::new(params),
+        Rc::new(body.to_vec()),
+        env.clone(),
+    ))
+}
 
-    let (params, body, func_env) = match lambda {
-        Object::Lambda(p, b, e) => {
-            if p.len() != 1 {
-                return Err(format!(
-                    "Invalid number of parameters for map lambda function {:?}",
-                    p
-                ));
-            }
-            (p, b, e)
+/// Parses the parameter list of a lambda: either a list of required
+/// parameters followed by optional `(name default)` parameters and an
+/// optional `. rest` parameter, or a single symbol taking all the arguments.
+fn parse_params(obj: &Object, list: &[Object]) -> Result<Params, LispError> {
+    let mut params = Params::default();
+    let items = match obj {
+        Object::Symbol(rest) => {
+            params.rest = Some(rest.clone());
+            return Ok(params);
         }
-        _ => return Err(format!("Not a lambda while evaluating map: {}", lambda)),
+        Object::List(items) => items,
+        _ => return Err(syntax_error("Invalid lambda", list)),
     };
+    let mut items = items.iter();
+    while let Some(param) = items.next() {
+        match param {
+            Object::Symbol(dot) if dot == "." => match (items.next(), items.next()) {
+                (Some(Object::Symbol(rest)), None) if rest != "." => {
+                    params.rest = Some(rest.clone())
+                }
+                _ => return Err(syntax_error("Invalid rest parameter", list)),
+            },
+            Object::Symbol(s) if params.optional.is_empty() => params.required.push(s.clone()),
+            Object::Symbol(_) => {
+                return Err(syntax_error(
+                    "Required parameter after an optional parameter",
+                    list,
+                ))
+            }
+            Object::List(optional) => match optional.as_slice() {
+                [Object::Symbol(name), default] => {
+                    params.optional.push((name.clone(), default.clone()))
+                }
+                _ => return Err(syntax_error("Invalid optional parameter", list)),
+            },
+            _ => return Err(LispError::type_error("parameter name", param)),
+        }
+    }
+    Ok(params)
+}
+
+/// Binds `args` to the parameters of a lambda in a new environment
+/// extending the lambda's closure. The defaults of missing optional
+/// arguments are evaluated in that environment, in order.
+fn bind_params(
+    params: &Params,
+    args: Vec<Object>,
+    func_env: &Rc<RefCell<Env>>,
+) -> Result<Rc<RefCell<Env>>, LispError> {
+    let arity = params.arity();
+    if !arity.accepts(args.len()) {
+        let name = params.name.as_deref().unwrap_or("lambda");
+        return Err(LispError::arity(name, arity, args.len()));
+    }
+    let mut new_env = Rc::new(RefCell::new(Env::extend(func_env.clone())));
+    let mut args = args.into_iter();
+    for param in params.required.iter() {
+        new_env.borrow_mut().set(param, args.next().unwrap());
+    }
+    for (param, default) in params.optional.iter() {
+        let value = match args.next() {
+            Some(arg) => arg,
+            None => eval_obj(default, &mut new_env)?,
+        };
+        new_env.borrow_mut().set(param, value);
+    }
+    if let Some(rest) = &params.rest {
+        new_env.borrow_mut().set(rest, Object::list(args.collect()));
+    }
+    Ok(new_env)
+}
+
+fn call_native(
+    name: &str,
+    arity: &Arity,
+    func: &NativeFunc,
+    args: &[Object],
+) -> Result<Object, LispError> {
+    if !arity.accepts(args.len()) {
+        return Err(LispError::arity(name, *arity, args.len()));
+    }
+    func.call(args)
+}
+
+/// Calls a lambda or native function with already evaluated arguments.
+pub fn apply(func: &Object, args: Vec<Object>) -> Result<Object, LispError> {
+    match func {
+        Object::Lambda(params, body, func_env) => {
+            let mut new_env = bind_params(params, args, func_env)?;
+            eval_sequence(body, &mut new_env)
+        }
+        Object::NativeFn(name, arity, native) => call_native(name, arity, native, &args),
+        _ => Err(LispError::type_error("procedure", func)),
+    }
+}
+
+fn eval_args(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Vec<Object>, LispError> {
+    let mut args = Vec::with_capacity(list.len());
+    for obj in list.iter() {
+        args.push(eval_obj(obj, env)?);
+    }
+    Ok(args)
+}
+
+/// Returns the value of the variable `name`, which may be an alias made by
+/// a `syntax-rules` expansion for a variable where the macro was defined.
+fn lookup(name: &str, env: &Rc<RefCell<Env>>) -> Option<Object> {
+    match resolve_alias(name) {
+        Some((name, def_env)) => def_env.borrow().get(&name),
+        None => env.borrow().get(name),
+    }
+}
 
-    let args = match arg_list {
-        Object::ListData(list) => list,
-        _ => return Err(format!("Invalid map arguments: {:?}", list)),
+fn eval_symbol(s: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let val = match s {
+        "#t" => return Ok(Object::Bool(true)),
+        "#f" => return Ok(Object::Bool(false)),
+        "#nil" => return Ok(Object::Void),
+        _ => lookup(s, env),
     };
 
-    let func_param = &params[0];
-    let mut result_list = Vec::new();
-    for arg in args.iter() {
-        let val = eval_obj(arg, env)?;
-        let mut new_env = Rc::new(RefCell::new(Env::extend(func_env.clone())));
-        new_env.borrow_mut().set(func_param, val);
-        let new_body = body.clone();
-        let result = eval_obj(&Object::List(new_body), &mut new_env)?;
-        result_list.push(result);
-    }
-    Ok(Object::ListData(result_list))
+    match val {
+        Some(Object::SpecialForm(_, _)) => Err(LispError::syntax(
+            "Special form used as a value",
+            &Object::Symbol(s.to_string()),
+        )),
+        Some(Object::Macro(_, _, _) | Object::SyntaxRules(_)) => Err(LispError::syntax(
+            "Macro used as a value",
+            &Object::Symbol(s.to_string()),
+        )),
+        Some(val) => Ok(val),
+        None => Err(LispError::unbound(s)),
+    }
 }
 
-fn eval_filter(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    if list.len() != 3 {
-        return Err(format!("Invalid number of arguments for filter {:?}", list));
+/// Converts a quoted form into the data it denotes: lists become chains of
+/// pairs and the symbols `#t`, `#f` and `#nil` their values. Other symbols
+/// remain symbols.
+fn quote_datum(obj: &Object) -> Object {
+    match obj {
+        Object::List(list) => {
+            let (items, tail) = split_dotted(list);
+            let tail = tail.map_or(Object::Null, quote_datum);
+            Object::list_with_tail(items.iter().map(quote_datum).collect(), tail)
+        }
+        Object::Symbol(s) if s == "#t" => Object::Bool(true),
+        Object::Symbol(s) if s == "#f" => Object::Bool(false),
+        Object::Symbol(s) if s == "#nil" => Object::Void,
+        _ => obj.clone(),
     }
+}
+
+fn eval_quote(list: &[Object]) -> Result<Object, LispError> {
+    check_arity(list, Arity::Exact(1))?;
+    Ok(quote_datum(&list[1]))
+}
 
-    let lambda = eval_obj(&list[1], env)?;
-    let arg_list = eval_obj(&list[2], env)?;
+fn eval_quasiquote(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    check_arity(list, Arity::Exact(1))?;
+    quasiquote(&list[1], 1, env)
+}
+
+/// Returns the name and argument of `obj` if it is one of the forms
+/// `(quasiquote x)`, `(unquote x)` or `(unquote-splicing x)`.
+fn quasiquote_form(obj: &Object) -> Option<(&str, &Object)> {
+    match obj {
+        Object::List(list) if list.len() == 2 => match &list[0] {
+            Object::Symbol(s) if s == "quasiquote" || s == "unquote" || s == "unquote-splicing" => {
+                Some((s.as_str(), &list[1]))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
 
-    let (params, body, func_env) = match lambda {
-        Object::Lambda(p, b, e) => {
-            if p.len() != 1 {
-                return Err(format!(
-                    "Invalid number of parameters for map function {:?}",
-                    p
-                ));
+/// Expands `template`, which is nested in `depth` quasiquotes. Only unquotes
+/// at depth 1 are evaluated, deeper ones are kept as data.
+fn quasiquote(
+    template: &Object,
+    depth: usize,
+    env: &mut Rc<RefCell<Env>>,
+) -> Result<Object, LispError> {
+    let list = match template {
+        Object::List(list) => list,
+        _ => return Ok(quote_datum(template)),
+    };
+    match quasiquote_form(template) {
+        Some(("unquote", arg)) if depth == 1 => return eval_obj(arg, env),
+        Some(("unquote-splicing", _)) if depth == 1 => {
+            return Err(syntax_error("unquote-splicing outside of a list", list))
+        }
+        Some((name, arg)) => {
+            let depth = if name == "quasiquote" {
+                depth + 1
+            } else {
+                depth - 1
+            };
+            return Ok(Object::list(vec![
+                Object::Symbol(name.to_string()),
+                quasiquote(arg, depth, env)?,
+            ]));
+        }
+        None => {}
+    }
+
+    let (template_items, tail) = split_dotted(list);
+    let mut items = Vec::new();
+    for item in template_items.iter() {
+        match quasiquote_form(item) {
+            Some(("unquote-splicing", arg)) if depth == 1 => {
+                let values = eval_obj(arg, env)?;
+                match values.list_items() {
+                    Some(values) => items.extend(values),
+                    None => return Err(LispError::type_error("proper list", &values)),
+                }
             }
-            (p, b, e)
+            _ => items.push(quasiquote(item, depth, env)?),
         }
-        _ => return Err(format!("Not a lambda while evaluating map: {}", lambda)),
+    }
+    let tail = match tail {
+        Some(tail) => quasiquote(tail, depth, env)?,
+        None => Object::Null,
     };
+    Ok(Object::list_with_tail(items, tail))
+}
 
-    let args = match arg_list {
-        Object::ListData(list) => list,
-        _ => return Err(format!("Invalid map arguments: {:?}", list)),
-    };
+/// Whether `obj` counts as true in a condition: every value but `#f` does.
+fn is_true(obj: &Object) -> bool {
+    !matches!(obj, Object::Bool(false))
+}
 
-    let func_param = &params[0];
-    let mut result_list = Vec::new();
-    for arg in args.iter() {
-        let val = eval_obj(arg, env)?;
-        let mut new_env = Rc::new(RefCell::new(Env::extend(func_env.clone())));
-        new_env.borrow_mut().set(func_param, val.clone());
-        let new_body = body.clone();
-        let result_obj = eval_obj(&Object::List(new_body), &mut new_env)?;
-        let result = match result_obj {
-            Object::Bool(b) => b,
-            _ => return Err(format!("Invalid filter result: {}", result_obj)),
-        };
-        if result {
-            result_list.push(val);
+/// A form evaluating to `value`, to pass a computed value to code evaluated
+/// in tail position.
+fn value_form(value: Object) -> Object {
+    match value {
+        Object::Symbol(_) | Object::List(_) => {
+            Object::List(Rc::new(vec![builtin_form("quote"), value]))
         }
+        _ => value,
     }
-    Ok(Object::ListData(result_list))
 }
 
-fn eval_reduce(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    if list.len() != 3 {
-        return Err(format!("Invalid number of arguments for reduce {:?}", list));
+/// `(if test then else)`, where `else` may be omitted: the if then
+/// evaluates to void when `test` is false.
+fn eval_if(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    check_arity(list, Arity::Range(2, 3))?;
+
+    if is_true(&eval_obj(&list[1], env)?) {
+        Ok(Step::Eval(list[2].clone(), env.clone()))
+    } else {
+        match list.get(3) {
+            Some(alternative) => Ok(Step::Eval(alternative.clone(), env.clone())),
+            None => Ok(Step::Value(Object::Void)),
+        }
     }
+}
 
-    let lambda = eval_obj(&list[1], env)?;
-    let arg_list = eval_obj(&list[2], env)?;
+/// `(when test body...)` evaluates `body` if `test` is true, and
+/// `(unless test body...)` if it is false.
+fn eval_when(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    check_arity(list, Arity::AtLeast(1))?;
+    let expected = matches!(&list[0], Object::Symbol(s) if source_name(s) == "when");
+    if is_true(&eval_obj(&list[1], env)?) == expected {
+        eval_body(&list[2..], env)
+    } else {
+        Ok(Step::Value(Object::Void))
+    }
+}
 
-    let (params, body, func_env) = match lambda {
-        Object::Lambda(p, b, e) => {
-            if p.len() != 2 {
-                return Err(format!(
-                    "Invalid number of parameters for reduce function {:?}",
-                    p
-                ));
-            }
-            (p, b, e)
+/// Evaluates the body of a `cond` or `case` clause whose test produced
+/// `value`. `(=> proc)` calls `proc` with `value`.
+fn eval_clause_body(
+    body: &[Object],
+    value: Object,
+    list: &[Object],
+    env: &mut Rc<RefCell<Env>>,
+) -> Result<Step, LispError> {
+    match body {
+        [Object::Symbol(arrow), proc] if source_name(arrow) == "=>" => {
+            let proc = eval_obj(proc, env)?;
+            let call = vec![proc, value_form(value)];
+            Ok(Step::Eval(Object::List(Rc::new(call)), env.clone()))
         }
-        _ => return Err(format!("Not a lambda while evaluating map: {}", lambda)),
-    };
+        [Object::Symbol(arrow), ..] if source_name(arrow) == "=>" => {
+            Err(syntax_error("Invalid => clause", list))
+        }
+        [] => Ok(Step::Value(value)),
+        _ => eval_body(body, env),
+    }
+}
 
-    let args = match arg_list {
-        Object::ListData(list) => list,
-        _ => return Err(format!("Invalid map arguments: {:?}", list)),
-    };
+fn is_else(obj: &Object) -> bool {
+    matches!(obj, Object::Symbol(s) if source_name(s) == "else")
+}
 
-    if args.len() < 2 {
-        return Err(format!(
-            "Invalid number of arguments for reduce: {:?}",
-            args
-        ));
+/// `(cond (test body...)... (else body...))` evaluates the body of the first
+/// clause whose test is true, or the value of the test if the body is empty.
+fn eval_cond(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    for (i, clause) in list[1..].iter().enumerate() {
+        let clause = match clause {
+            Object::List(clause) if !clause.is_empty() => clause,
+            _ => return Err(syntax_error("Invalid cond clause", list)),
+        };
+        if is_else(&clause[0]) {
+            if i != list.len() - 2 || clause.len() < 2 {
+                return Err(syntax_error("Invalid else clause in cond", list));
+            }
+            return eval_body(&clause[1..], env);
+        }
+        let value = eval_obj(&clause[0], env)?;
+        if is_true(&value) {
+            return eval_clause_body(&clause[1..], value, list, env);
+        }
+    }
+    Ok(Step::Value(Object::Void))
+}
+
+/// `(case key ((datum...) body...)... (else body...))` evaluates the body of
+/// the first clause listing the value of `key`.
+fn eval_case(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    check_arity(list, Arity::AtLeast(1))?;
+    let key = eval_obj(&list[1], env)?;
+    for (i, clause) in list[2..].iter().enumerate() {
+        let clause = match clause {
+            Object::List(clause) if clause.len() >= 2 => clause,
+            _ => return Err(syntax_error("Invalid case clause", list)),
+        };
+        let matched = match &clause[0] {
+            datum if is_else(datum) => {
+                if i != list.len() - 3 {
+                    return Err(syntax_error("Invalid else clause in case", list));
+                }
+                true
+            }
+            Object::List(data) => data.iter().any(|datum| quote_datum(datum) == key),
+            _ => return Err(syntax_error("Invalid case clause", list)),
+        };
+        if matched {
+            return eval_clause_body(&clause[1..], key, list, env);
+        }
     }
+    Ok(Step::Value(Object::Void))
+}
 
-    let reduce_param1 = &params[0];
-    let reduce_param2 = &params[1];
-    let mut accumulator = eval_obj(&args[0], env)?;
+/// `(and test...)` evaluates the tests in order and stops at the first false
+/// one; `(or test...)` stops at the first true one. The value is the one of
+/// the last test evaluated, which is in tail position.
+fn eval_and_or(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let is_and = matches!(&list[0], Object::Symbol(s) if source_name(s) == "and");
+    let (last, init) = match list[1..].split_last() {
+        Some(split) => split,
+        None => return Ok(Step::Value(Object::Bool(is_and))),
+    };
+    for test in init {
+        let value = eval_obj(test, env)?;
+        if is_true(&value) != is_and {
+            return Ok(Step::Value(value));
+        }
+    }
+    Ok(Step::Eval(last.clone(), env.clone()))
+}
 
-    for arg in args[1..].iter() {
-        let mut new_env = Rc::new(RefCell::new(Env::extend(func_env.clone())));
-        new_env.borrow_mut().set(reduce_param1, accumulator.clone());
+type BuiltinFormFn = fn(&[Object], &mut Rc<RefCell<Env>>) -> Result<Step, LispError>;
 
-        let val = eval_obj(arg, env)?;
-        new_env.borrow_mut().set(reduce_param2, val.clone());
+struct BuiltinForm(BuiltinFormFn);
 
-        let new_body = body.clone();
-        accumulator = eval_obj(&Object::List(new_body), &mut new_env)?;
+impl SpecialForm for BuiltinForm {
+    fn eval(&self, form: &[Object], env: &Rc<RefCell<Env>>) -> Result<Step, LispError> {
+        (self.0)(form, &mut env.clone())
     }
-    Ok(accumulator)
 }
 
-fn eval_symbol(s: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let val = match s {
-        "#t" => return Ok(Object::Bool(true)),
-        "#f" => return Ok(Object::Bool(false)),
-        "#nil" => return Ok(Object::Void),
-        _ => env.borrow_mut().get(s),
-    };
+const SPECIAL_FORMS: &[(&str, BuiltinFormFn)] = &[
+    ("define", |list, env| {
+        eval_define(list, env).map(Step::Value)
+    }),
+    ("set!", |list, env| eval_set(list, env).map(Step::Value)),
+    ("begin", eval_begin),
+    ("let", eval_let),
+    ("let*", eval_let_star),
+    ("letrec", eval_letrec),
+    ("letrec*", eval_letrec),
+    ("do", eval_do),
+    ("lambda", |list, env| {
+        eval_function_definition(list, env).map(Step::Value)
+    }),
+    ("if", eval_if),
+    ("when", eval_when),
+    ("unless", eval_when),
+    ("cond", eval_cond),
+    ("case", eval_case),
+    ("and", eval_and_or),
+    ("or", eval_and_or),
+    ("else", |list, _| {
+        Err(syntax_error("else outside of cond or case", list))
+    }),
+    ("=>", |list, _| {
+        Err(syntax_error("=> outside of cond or case", list))
+    }),
+    ("quote", |list, _| eval_quote(list).map(Step::Value)),
+    ("define-macro", |list, env| {
+        eval_define_macro(list, env).map(Step::Value)
+    }),
+    ("macroexpand", |list, env| {
+        eval_macroexpand(list, env).map(Step::Value)
+    }),
+    ("macroexpand-1", |list, env| {
+        eval_macroexpand(list, env).map(Step::Value)
+    }),
+    ("syntax-rules", |list, env| {
+        eval_syntax_rules(list, env).map(Step::Value)
+    }),
+    ("define-syntax", |list, env| {
+        eval_define_syntax(list, env).map(Step::Value)
+    }),
+    ("let-syntax", eval_let_syntax),
+    ("quasiquote", |list, env| {
+        eval_quasiquote(list, env).map(Step::Value)
+    }),
+    ("unquote", |list, _| {
+        Err(syntax_error("unquote outside of quasiquote", list))
+    }),
+    ("unquote-splicing", |list, _| {
+        Err(syntax_error("unquote-splicing outside of quasiquote", list))
+    }),
+];
+
+/// The built-in special form `name`, for forms to build code that uses it
+/// whatever `name` is bound to where the code is evaluated.
+fn builtin_form(name: &str) -> Object {
+    let (_, func) = SPECIAL_FORMS.iter().find(|(n, _)| *n == name).unwrap();
+    Object::SpecialForm(name.to_string(), NativeForm::new(BuiltinForm(*func)))
+}
 
-    if val.is_none() {
-        return Err(format!("Unbound symbol: {}", s));
+/// Binds the built-in special forms in `env`. Like any other binding, they
+/// can be shadowed or removed.
+pub fn define_special_forms(env: &mut Env) {
+    for (name, func) in SPECIAL_FORMS {
+        let form = Object::SpecialForm(name.to_string(), NativeForm::new(BuiltinForm(*func)));
+        env.set(name, form);
     }
+}
 
-    Ok(val.unwrap().clone())
+/// What evaluating a special form produces: either its final value, or a
+/// form left to evaluate in tail position in the given environment.
+pub enum Step {
+    Value(Object),
+    Eval(Object, Rc<RefCell<Env>>),
 }
 
-fn eval_keyword(list: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let head = &list[0];
-    match head {
-        Object::Keyword(s) => match s.as_str() {
-            "define" => eval_define(list, env),
-            "begin" => eval_begin(list, env),
-            "let" => eval_let(list, env),
-            "list" => eval_list_data(list, env),
-            "print" => print_list(list, env),
-            "lambda" => eval_function_definition(list, env),
-            "map" => eval_map(list, env),
-            "filter" => eval_filter(list, env),
-            "reduce" => eval_reduce(list, env),
-            "range" => eval_range(list, env),
-            "car" => eval_car(list, env),
-            "cdr" => eval_cdr(list, env),
-            "length" => eval_length(list, env),
-            "null?" => eval_is_null(list, env),
-            _ => Err(format!("Unknown keyword: {}", s)),
-        },
-        _ => Err(format!("Invalid keyword: {}", head)),
+fn eval_list(list: &Rc<Vec<Object>>, env: &mut Rc<RefCell<Env>>) -> Result<Step, LispError> {
+    let head = match list.first() {
+        Some(head) => head,
+        None => return Err(syntax_error("Empty application", list)),
+    };
+    let func = match head {
+        Object::Symbol(s) => lookup(s, env).ok_or_else(|| LispError::unbound(s))?,
+        _ => eval_obj(head, env)?,
+    };
+    match func {
+        Object::SpecialForm(_, form) => form.eval(list, env),
+        Object::Macro(ref params, ref body, ref macro_env) => {
+            let expansion =
+                expand_once(list, &func, || expand_macro(params, body, macro_env, list))?;
+            Ok(Step::Eval(expansion, env.clone()))
+        }
+        Object::SyntaxRules(rules) => {
+            let expansion = expand_syntax_rules(&rules, list, env)?;
+            Ok(Step::Eval(expansion, env.clone()))
+        }
+        Object::Lambda(params, body, func_env) => {
+            let args = eval_args(&list[1..], env)?;
+            let mut new_env = bind_params(&params, args, &func_env)?;
+            eval_body(&body, &mut new_env)
+        }
+        Object::NativeFn(name, arity, func) => {
+            let args = eval_args(&list[1..], env)?;
+            call_native(&name, &arity, &func, &args).map(Step::Value)
+        }
+        _ => Err(LispError::type_error("procedure", &func)),
+    }
+}
+
+/// Attaches the location of `list` to an error raised while evaluating it,
+/// unless a more precise location is already known.
+fn locate(err: LispError, list: &Rc<Vec<Object>>) -> LispError {
+    if err.span().is_some() {
+        return err;
+    }
+    match span_of(list) {
+        Some(span) => err.with_span(span),
+        None => err,
     }
 }
 
-fn eval_obj(obj: &Object, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
+fn eval_obj(obj: &Object, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
     let mut current_obj = Box::new(obj.clone());
     let mut current_env = env.clone();
     loop {
         match *current_obj {
-            Object::List(list) => {
-                let head = &list[0];
-                match head {
-                    Object::BinaryOp(_op) => {
-                        return eval_binary_op(&list, &mut current_env);
-                    }
-                    Object::Keyword(_keyword) => {
-                        if _keyword == "if" {
-                            if list.len() != 4 {
-                                return Err(
-                                    "Invalid number of arguments for if statement".to_string()
-                                );
-                            }
-
-                            let cond_obj = eval_obj(&list[1], &mut current_env)?;
-                            let cond = match cond_obj {
-                                Object::Bool(b) => b,
-                                _ => return Err("Condition must be a boolean".to_string()),
-                            };
-
-                            if cond {
-                                current_obj = Box::new(list[2].clone());
-                            } else {
-                                current_obj = Box::new(list[3].clone());
-                            }
-                            continue;
-                        } else {
-                            return eval_keyword(&list, &mut current_env);
-                        }
-                    }
-                    Object::Lambda(params, body, func_env) => {
-                        let new_env = Rc::new(RefCell::new(Env::extend(func_env.clone())));
-                        for (i, param) in params.iter().enumerate() {
-                            let val = eval_obj(&list[i + 1], &mut current_env)?;
-                            new_env.borrow_mut().set(param, val);
-                        }
-                        current_obj = Box::new(Object::List(body.clone()));
-                        current_env = new_env;
-                        continue;
-                    }
-                    Object::Symbol(s) => {
-                        let lamdba = current_env.borrow_mut().get(s);
-                        if lamdba.is_none() {
-                            return Err(format!("Unbound function: {}", s));
-                        }
-
-                        let func = lamdba.unwrap();
-                        match func {
-                            Object::Lambda(params, body, func_env) => {
-                                let new_env = Rc::new(RefCell::new(Env::extend(func_env.clone())));
-                                for (i, param) in params.iter().enumerate() {
-                                    let val = eval_obj(&list[i + 1], &mut current_env)?;
-                                    new_env.borrow_mut().set(param, val);
-                                }
-                                current_obj = Box::new(Object::List(body));
-                                current_env = new_env.clone();
-                                continue;
-                            }
-                            _ => return Err(format!("Not a lambda: {} {:?}", s, func)),
-                        }
-                    }
-                    _ => {
-                        let mut new_list = Vec::new();
-                        for obj in (*list).iter() {
-                            let result = eval_obj(obj, &mut current_env)?;
-                            match result {
-                                Object::Void => {}
-                                _ => new_list.push(result),
-                            }
-                        }
-                        let head = &new_list[0];
-                        match head {
-                            Object::Lambda(_, _, _) => {
-                                return eval_obj(
-                                    &Object::List(Rc::new(new_list)),
-                                    &mut current_env,
-                                );
-                            }
-                            _ => {
-                                return Ok(Object::List(Rc::new(new_list)));
-                            }
-                        }
-                    }
+            Object::List(list) => match eval_list(&list, &mut current_env) {
+                Ok(Step::Value(val)) => return Ok(val),
+                Ok(Step::Eval(obj, env)) => {
+                    *current_obj = obj;
+                    current_env = env;
                 }
-            }
+                Err(e) => return Err(locate(e, &list)),
+            },
             Object::Symbol(s) => {
                 return eval_symbol(&s, &mut current_env);
             }
             Object::Void => return Ok(Object::Void),
-            Object::Lambda(_params, _body, _func_env) => return Ok(Object::Void),
-            Object::Bool(_) => return Ok(obj.clone()),
+            Object::Lambda(params, body, func_env) => {
+                return Ok(Object::Lambda(params, body, func_env))
+            }
+            Object::NativeFn(name, arity, func) => return Ok(Object::NativeFn(name, arity, func)),
+            Object::SpecialForm(name, form) => return Ok(Object::SpecialForm(name, form)),
+            Object::Macro(params, body, macro_env) => {
+                return Ok(Object::Macro(params, body, macro_env))
+            }
+            Object::SyntaxRules(rules) => return Ok(Object::SyntaxRules(rules)),
+            Object::Bool(b) => return Ok(Object::Bool(b)),
             Object::Integer(n) => return Ok(Object::Integer(n)),
             Object::Float(n) => return Ok(Object::Float(n)),
             Object::String(s) => return Ok(Object::String(s.to_string())),
-            Object::ListData(l) => return Ok(Object::ListData(l.to_vec())),
-            _ => return Err(format!("Invalid object: {:?}", obj)),
+            Object::Null => return Ok(Object::Null),
+            Object::Pair(pair) => return Ok(Object::Pair(pair)),
         }
     }
 }
 
-pub fn eval(program: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, String> {
-    let parsed_list = parse(program);
-    if parsed_list.is_err() {
-        return Err(format!("{}", parsed_list.err().unwrap()));
+/// Evaluates `form` in `env`. Special forms implemented in Rust use it to
+/// evaluate their arguments.
+pub fn eval_form(form: &Object, env: &Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    eval_obj(form, &mut env.clone())
+}
+
+/// Evaluates the top-level forms of a program in order and returns the
+/// value of the last one.
+fn eval_program(forms: &[Object], env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let mut result = Object::Void;
+    for form in forms {
+        result = eval_obj(form, env)?;
     }
-    eval_obj(&parsed_list.unwrap(), env)
+    Ok(result)
+}
+
+pub fn eval(program: &str, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let forms = parse(program)?;
+    eval_program(&forms, env)
+}
+
+pub fn eval_source(source: &Rc<Source>, env: &mut Rc<RefCell<Env>>) -> Result<Object, LispError> {
+    let forms = parse_program(source)?;
+    eval_program(&forms, env)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::builtins::base_env;
+
+    // Far too small for a Lisp call or a list to recurse 10000 levels deep.
+    const SMALL_STACK: usize = 256 * 1024;
+
+    /// Runs `test` on a thread with a small stack, so that loops and long
+    /// lists only pass if they run in constant stack space.
+    fn with_small_stack(test: impl FnOnce() + Send + 'static) {
+        let thread = std::thread::Builder::new()
+            .stack_size(SMALL_STACK)
+            .spawn(test)
+            .unwrap();
+        if let Err(panic) = thread.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
 
     #[test]
     fn test_simple_add() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(+ 1 2)", &mut env).unwrap();
         assert_eq!(result, Object::Integer(3));
     }
 
     #[test]
     fn test_simple_sub() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(- 1.0 2)", &mut env).unwrap();
         assert_eq!(result, Object::Float(-1.0));
     }
 
+    #[test]
+    fn test_negative_literals() {
+        let mut env = base_env();
+        let result = eval("(- -5 #x-A)", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(5));
+        let result = eval("(* -1.5e2 2)", &mut env).unwrap();
+        assert_eq!(result, Object::Float(-300.0));
+    }
+
     #[test]
     fn test_str_add() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(+ \"Raleigh\" \"Durham\")", &mut env).unwrap();
         assert_eq!(result, Object::String("RaleighDurham".to_string()));
     }
 
     #[test]
     fn test_str_eq_false() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(= \"Raleigh\" \"Durham\")", &mut env).unwrap();
         assert_eq!(result, Object::Bool(false));
     }
 
     #[test]
     fn test_str_eq_true() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(= \"Raleigh\" \"Raleigh\")", &mut env).unwrap();
         assert_eq!(result, Object::Bool(true));
     }
 
     #[test]
     fn test_greater_than_str() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(> \"Raleigh\" \"Durham\")", &mut env).unwrap();
         assert_eq!(result, Object::Bool(true));
     }
 
     #[test]
     fn test_less_than_str() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(< \"abcd\" \"abef\")", &mut env).unwrap();
         assert_eq!(result, Object::Bool(true));
     }
 
     #[test]
     fn test_str_with_spaces() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(+ \"Raleigh \" \"Durham\")", &mut env).unwrap();
         assert_eq!(result, Object::String("Raleigh Durham".to_string()));
     }
 
     #[test]
     fn test_str_with_spaces_2() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
-        (
             (define fruits \"apples mangoes bananas \")
             (define vegetables \"carrots broccoli\")
             (+ fruits vegetables)
-        )
         ";
         let result = eval(program, &mut env).unwrap();
         assert_eq!(
             result,
-            Object::List(Rc::new(vec![Object::String(
-                "apples mangoes bananas carrots broccoli".to_string()
-            )]))
+            Object::String("apples mangoes bananas carrots broccoli".to_string())
         );
     }
 
     #[test]
     fn test_greater_than_int() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(> 10 20)", &mut env).unwrap();
         assert_eq!(result, Object::Bool(false));
     }
 
     #[test]
     fn test_less_than_int() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(< 21.0 20.0)", &mut env).unwrap();
         assert_eq!(result, Object::Bool(false));
     }
 
     #[test]
     fn test_modulo() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let result = eval("(% 21.0 20.0)", &mut env).unwrap();
         assert_eq!(result, Object::Float(1.0));
     }
 
     #[test]
-    fn test_area_of_a_circle_float() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_variadic_add() {
+        let mut env = base_env();
+        let program = "(list (+ 1 2 3 4) (+) (+ 1 2 0.5))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![
+                Object::Integer(10),
+                Object::Integer(0),
+                Object::Float(3.5),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_variadic_str_add() {
+        let mut env = base_env();
+        let result = eval("(+ \"a\" \"b\" \"c\")", &mut env).unwrap();
+        assert_eq!(result, Object::String("abc".to_string()));
+    }
+
+    #[test]
+    fn test_variadic_mul() {
+        let mut env = base_env();
+        let result = eval("(list (* 2 3 4) (*))", &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![Object::Integer(24), Object::Integer(1)])
+        );
+    }
+
+    #[test]
+    fn test_variadic_sub_and_negation() {
+        let mut env = base_env();
+        let result = eval("(list (- 10 1 2) (- 5) (- 2.5))", &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![
+                Object::Integer(7),
+                Object::Integer(-5),
+                Object::Float(-2.5),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_variadic_div_and_reciprocal() {
+        let mut env = base_env();
+        let result = eval("(list (/ 100 5 2) (/ 4.0))", &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![Object::Integer(10), Object::Float(0.25)])
+        );
+    }
+
+    #[test]
+    fn test_sub_without_args() {
+        let mut env = base_env();
+        let result = eval("(-)", &mut env);
+        assert!(matches!(result, Err(LispError::Arity { .. })));
+    }
+
+    #[test]
+    fn test_div_by_zero_after_first_arg() {
+        let mut env = base_env();
+        let result = eval("(/ 1 2 0)", &mut env);
+        assert!(matches!(result, Err(LispError::DivisionByZero { .. })));
+    }
+
+    #[test]
+    fn test_add_str_and_int() {
+        let mut env = base_env();
+        let result = eval("(+ \"a\" 1)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+    }
+
+    #[test]
+    fn test_chained_less_than() {
+        let mut env = base_env();
+        let result = eval("(list (< 1 2 3) (< 1 3 2) (<= 1 1 2))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(true false true)");
+    }
+
+    #[test]
+    fn test_chained_greater_than() {
+        let mut env = base_env();
+        let result = eval("(list (>= 3 3 1) (>= 3 4) (> 3 2.5 2))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(true false true)");
+    }
+
+    #[test]
+    fn test_chained_equality() {
+        let mut env = base_env();
+        let program = "(list (= 1 1.0 1) (= 2 2 3) (!= 1 2) (!= 1 1.0))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(true false true false)");
+    }
+
+    #[test]
+    fn test_chained_str_comparisons() {
+        let mut env = base_env();
+        let program = "(list (!= \"a\" \"b\") (<= \"a\" \"a\" \"b\"))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(true true)");
+    }
+
+    #[test]
+    fn test_nan_comparisons() {
+        let mut env = base_env();
+        let result = eval("(list (< 1 +nan.0) (!= +nan.0 +nan.0))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(false true)");
+    }
+
+    #[test]
+    fn test_variadic_logic() {
+        let mut env = base_env();
+        let result = eval("(list (& #t #t #f) (| #f #f #t))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(false true)");
+    }
+
+    #[test]
+    fn test_comparison_with_one_arg() {
+        let mut env = base_env();
+        let result = eval("(< 1)", &mut env);
+        assert!(matches!(result, Err(LispError::Arity { .. })));
+    }
+
+    #[test]
+    fn test_comparison_of_int_and_str() {
+        let mut env = base_env();
+        let result = eval("(< 1 \"a\")", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_area_of_a_circle_float() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define r 5.0)
+                (define pi 3.14)
+                (* pi (* r r))
+            )";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Float(3.14 * 5.0 * 5.0));
+    }
+
+    #[test]
+    fn test_range_no_stride() {
+        let mut env = base_env();
+        let program = "(range 0 11)";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![
+                Object::Integer(0),
+                Object::Integer(1),
+                Object::Integer(2),
+                Object::Integer(3),
+                Object::Integer(4),
+                Object::Integer(5),
+                Object::Integer(6),
+                Object::Integer(7),
+                Object::Integer(8),
+                Object::Integer(9),
+                Object::Integer(10)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_range_with_stride() {
+        let mut env = base_env();
+        let program = "(range 0 10 3)";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![
+                Object::Integer(0),
+                Object::Integer(3),
+                Object::Integer(6),
+                Object::Integer(9),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_range_near_integer_limit() {
+        let mut env = base_env();
+        let program = "(range 9223372036854775800 9223372036854775807 5)";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![
+                Object::Integer(9223372036854775800),
+                Object::Integer(9223372036854775805),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_area_of_a_circle() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define r 10)
+                (define pi 314)
+                (* pi (* r r))
+            )";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer((314 * 10 * 10) as i64));
+    }
+
+    #[test]
+    fn test_sqr_function() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define sqr (lambda (r) (* r r))) 
+                (sqr 10)
+            )";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer((10 * 10) as i64));
+    }
+
+    #[test]
+    fn test_map() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define sqr (lambda (r) (* r r)))
+                (define l (list 1 2 3 4 5))
+                (map sqr l)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![
+                Object::Integer(1),
+                Object::Integer(4),
+                Object::Integer(9),
+                Object::Integer(16),
+                Object::Integer(25)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define odd (lambda (v) (= 1 (% v 2))))
+                (define l (list 1 2 3 4 5))
+                (filter odd l)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![
+                Object::Integer(1),
+                Object::Integer(3),
+                Object::Integer(5)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_reduce() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define odd (lambda (v) (= 1 (% v 2))))
+                (define l (list 1 2 3 4 5))
+                (reduce (lambda (x y) (| x y)) (map odd l))
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Bool(true));
+    }
+
+    #[test]
+    fn test_fibonaci() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define fib (lambda (n) 
+                    (if (< n 2) 1 
+                        (+ (fib (- n 1)) 
+                            (fib (- n 2))))))
+                (fib 10)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(89));
+    }
+
+    #[test]
+    fn test_factorial() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define fact (lambda (n) (if (< n 1) 1 (* n (fact (- n 1))))))
+                (fact 5)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(120));
+    }
+
+    #[test]
+    fn test_circle_area_no_lambda() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define pi 314)
+                (define r 10)
+                (define (sqr r) (* r r))
+                (define (area r) (* pi (sqr r)))
+                (area r)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer((314 * 10 * 10) as i64));
+    }
+
+    #[test]
+    fn test_circle_area_function() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define pi 314)
+                (define r 10)
+                (define sqr (lambda (r) (* r r)))
+                (define area (lambda (r) (* pi (sqr r))))
+                (area r)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer((314 * 10 * 10) as i64));
+    }
+
+    #[test]
+    fn test_tail_recursion() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define sum-n 
+                   (lambda (n a) 
+                      (if (= n 0) a 
+                          (sum-n (- n 1) (+ n a)))))
+                (sum-n 500 0)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(125250));
+    }
+
+    #[test]
+    fn test_tail_recursive_factorial() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define fact 
+                    (lambda (n a) 
+                      (if (= n 1) a 
+                        (fact (- n 1) (* n a)))))
+                        
+                (fact 10 1)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(3628800));
+    }
+
+    #[test]
+    fn test_closure1() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define add-n 
+                   (lambda (n) 
+                      (lambda (a) (+ n a))))
+                (define add-5 (add-n 5))
+                (add-5 10)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(15));
+    }
+
+    #[test]
+    fn test_tail_recursive_fibonnaci() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define fib
+                  (lambda (n a b) 
+                     (if (= n 0) a 
+                       (if (= n 1) b 
+                          (fib (- n 1) b (+ a b))))))
+                  
+                (fib 10 0 1)
+            )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(55));
+    }
+
+    #[test]
+    fn test_inline_lambda() {
+        let mut env = base_env();
+        let program = "
+        (begin
+            ((lambda (x y) (+ x y)) 10 20)
+        )
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(30));
+    }
+
+    #[test]
+    fn test_reduce_with_builtin() {
+        let mut env = base_env();
+        let program = "(list (reduce + (list 1 2 3)) (reduce * (range 1 5)))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(6 24)");
+    }
+
+    #[test]
+    fn test_map_with_builtin() {
+        let mut env = base_env();
+        let program = "(list (map car (list (list 1 2) (list 3 4))) (map - (list 1 2)))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "((1 3) (-1 -2))");
+    }
+
+    #[test]
+    fn test_define_builtin_alias() {
+        let mut env = base_env();
+        let result = eval("(begin (define add +) (add 1 2 3))", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(6));
+    }
+
+    #[test]
+    fn test_return_builtin_from_function() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define (pick n) (if (= n 0) length car))
+                ((pick 0) (list 1 2)))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(2));
+    }
+
+    #[test]
+    fn test_pass_builtin_to_lambda() {
+        let mut env = base_env();
+        let result = eval("((lambda (f) (f 10 4)) -)", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(6));
+    }
+
+    #[test]
+    fn test_builtin_as_result() {
+        let mut env = base_env();
+        let program = "(begin (define f (lambda (x) (if #t x x))) (f car))";
+        let result = eval(program, &mut env).unwrap();
+        assert!(matches!(result, Object::NativeFn(name, _, _) if name == "car"));
+    }
+
+    #[test]
+    fn test_special_form_as_value() {
+        let mut env = base_env();
+        let result = eval("(map define (list 1))", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_shadow_builtin_with_param() {
+        let mut env = base_env();
+        let result = eval("((lambda (list) (car list)) (list 1 2))", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(1));
+    }
+
+    #[test]
+    fn test_shadow_builtin_with_define() {
+        let mut env = base_env();
+        let result = eval("(begin (define range 5) (+ range 1))", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(6));
+    }
+
+    #[test]
+    fn test_shadow_special_form_with_param() {
+        let mut env = base_env();
+        let result = eval("((lambda (if) (if 1 2)) +)", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(3));
+    }
+
+    #[test]
+    fn test_shadow_builtin_with_let() {
+        let mut env = base_env();
+        let result = eval("(let ((car cdr)) (car (list 1 2)))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(2)");
+    }
+
+    #[test]
+    fn test_builtins_unbound_in_empty_env() {
+        let mut env = Rc::new(RefCell::new(Env::new()));
+        let result = eval("(car (list 1))", &mut env);
+        assert!(matches!(result, Err(LispError::UnboundSymbol { .. })));
+    }
+
+    #[test]
+    fn test_quote_symbol() {
+        let mut env = base_env();
+        let result = eval("(begin (quote x))", &mut env).unwrap();
+        assert_eq!(result, Object::Symbol("x".to_string()));
+    }
+
+    #[test]
+    fn test_quote_list() {
+        let mut env = base_env();
+        let result = eval("(begin (car '(a b)))", &mut env).unwrap();
+        assert_eq!(result, Object::Symbol("a".to_string()));
+    }
+
+    #[test]
+    fn test_quote_nested_list() {
+        let mut env = base_env();
+        let result = eval("(begin (cdr '(1 (x \"s\") #t)))", &mut env).unwrap();
+        let expected = Object::list(vec![
+            Object::list(vec![
+                Object::Symbol("x".to_string()),
+                Object::String("s".to_string()),
+            ]),
+            Object::Bool(true),
+        ]);
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_quote_empty_list() {
+        let mut env = base_env();
+        let result = eval("(begin (null? '()))", &mut env).unwrap();
+        assert_eq!(result, Object::Bool(true));
+    }
+
+    #[test]
+    fn test_quote_quote() {
+        let mut env = base_env();
+        let result = eval("(begin '(quote x))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(quote x)");
+    }
+
+    #[test]
+    fn test_quoted_symbol_not_evaluated() {
+        let mut env = base_env();
+        let program = "(begin ((lambda (s) (if #t s s)) 'undefined-variable))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Symbol("undefined-variable".to_string()));
+    }
+
+    #[test]
+    fn test_quasiquote_unquote_splicing() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define b 2)
+                (define c (list 3 4))
+                `(a ,b ,@c))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(a 2 3 4)");
+    }
+
+    #[test]
+    fn test_quasiquote_nested_unquote() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define b 2)
+                `(1 ,(+ b 1) (nested ,b)))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(1 3 (nested 2))");
+    }
+
+    #[test]
+    fn test_quasiquote_splice_empty_list() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define c (list 3 4))
+                `(,@c ,@'() end))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(3 4 end)");
+    }
+
+    #[test]
+    fn test_quasiquote_atoms() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define b 2)
+                (list `x `,b))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(x 2)");
+    }
+
+    #[test]
+    fn test_nested_quasiquote() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define b 2)
+                `(1 `(2 ,(3 ,b))))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(1 (quasiquote (2 (unquote (3 2)))))");
+    }
+
+    #[test]
+    fn test_unquote_splicing_non_list() {
+        let mut env = base_env();
+        let result = eval("(begin (define b 2) `(,@b))", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+    }
+
+    #[test]
+    fn test_unquote_splicing_outside_list() {
+        let mut env = base_env();
+        let result = eval("(begin (define c (list 3 4)) `,@c)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_unquote_outside_quasiquote() {
+        let mut env = base_env();
+        let result = eval("(begin (define b 2) ,b)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_define_macro() {
+        let mut env = base_env();
+        let program = "
+            (define-macro (unless c then else) `(if ,c ,else ,then))
+            (unless (> 1 2) \"yes\" \"no\")
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::String("yes".to_string()));
+    }
+
+    #[test]
+    fn test_define_macro_nested_calls() {
+        let mut env = base_env();
+        let program = "
+            (define-macro (-> x f) `(,(car f) ,x ,@(cdr f)))
+            (list (-> 10 (- 3)) (-> (-> 2 (* 5)) (+ 1 1)))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(7 12)");
+    }
+
+    #[test]
+    fn test_define_macro_with_gensym() {
+        let mut env = base_env();
+        let program = "
+            (define-macro (my-or a b)
+              (let ((tmp (gensym)))
+                `(let ((,tmp ,a)) (if ,tmp ,tmp ,b))))
+            (let ((tmp #t)) (my-or #f tmp))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Bool(true));
+    }
+
+    #[test]
+    fn test_macro_as_value() {
+        let mut env = base_env();
+        let program = "
+            (define-macro (unless c then else) `(if ,c ,else ,then))
+            (map unless (list 1))
+        ";
+        let result = eval(program, &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_macro_expanded_once_per_call() {
+        let mut env = base_env();
+        let program = "
+            (define expansions 0)
+            (define-macro (m) (set! expansions (+ expansions 1)) 1)
+            (define (h) (m))
+            (h)
+            (h)
+        ";
+        eval(program, &mut env).unwrap();
+        let result = eval("expansions", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(1));
+
+        // Redefining the macro expands the call again.
+        eval("(define-macro (m) 2)", &mut env).unwrap();
+        assert_eq!(eval("(h)", &mut env).unwrap(), Object::Integer(2));
+    }
+
+    #[test]
+    fn test_macroexpand_1() {
+        let mut env = base_env();
+        let program = "
+            (define-macro (unless c then else) `(if ,c ,else ,then))
+            (define-macro (unless2 c then else) `(unless ,c ,then ,else))
+            (macroexpand-1 '(unless2 a b c))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(unless a b c)");
+    }
+
+    #[test]
+    fn test_macroexpand() {
+        let mut env = base_env();
+        let program = "
+            (define-macro (unless c then else) `(if ,c ,else ,then))
+            (define-macro (unless2 c then else) `(unless ,c ,then ,else))
+            (macroexpand '(unless2 a b c))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(if a c b)");
+    }
+
+    #[test]
+    fn test_macroexpand_non_macro() {
+        let mut env = base_env();
+        let program = "(list (macroexpand '(+ 1 2)) (macroexpand 'x))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "((+ 1 2) x)");
+    }
+
+    #[test]
+    fn test_cond() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define (sign n)
+                  (cond ((< n 0) 'negative)
+                        ((= n 0) 'zero)
+                        (else 'positive)))
+                (list (sign -2) (sign 0) (sign 5)))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(negative zero positive)");
+    }
+
+    #[test]
+    fn test_cond_without_match() {
+        let mut env = base_env();
+        let result = eval("(cond (#f 1))", &mut env).unwrap();
+        assert_eq!(result, Object::Void);
+    }
+
+    #[test]
+    fn test_cond_clause_without_body() {
+        let mut env = base_env();
+        let result = eval("(cond ((+ 1 2)))", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(3));
+    }
+
+    #[test]
+    fn test_cond_arrow() {
+        let mut env = base_env();
+        let program = "
+            (list (cond ((car (list 1 2)) => (lambda (x) (+ x 10))))
+                  (cond ((car (list 'a)) => (lambda (x) (list x)))))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(11 (a))");
+    }
+
+    #[test]
+    fn test_case() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define (kind x)
+                  (case x
+                    ((1 2 3) 'small)
+                    ((a b) 'letter)
+                    (else 'other)))
+                (list (kind 2) (kind 'b) (kind \"c\")))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(small letter other)");
+    }
+
+    #[test]
+    fn test_case_without_match() {
+        let mut env = base_env();
+        let result = eval("(case 5 ((1) 'one))", &mut env).unwrap();
+        assert_eq!(result, Object::Void);
+    }
+
+    #[test]
+    fn test_case_else_arrow() {
+        let mut env = base_env();
+        let result = eval("(case 5 (else => (lambda (x) (* x x))))", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(25));
+    }
+
+    #[test]
+    fn test_one_armed_if() {
+        let mut env = base_env();
+        let result = eval("(if #f 1)", &mut env).unwrap();
+        assert_eq!(result, Object::Void);
+    }
+
+    #[test]
+    fn test_if_non_boolean_test() {
+        let mut env = base_env();
+        let result = eval("(if 0 'yes 'no)", &mut env).unwrap();
+        assert_eq!(result, Object::Symbol("yes".to_string()));
+    }
+
+    #[test]
+    fn test_when_and_unless() {
+        let mut env = base_env();
+        let result = eval("(list (when (> 2 1) 'a 'b) (unless #f 'a))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(b a)");
+    }
+
+    #[test]
+    fn test_when_and_unless_skipped() {
+        let mut env = base_env();
+        let result = eval("(list (when #f 'a) (unless #t 'a))", &mut env).unwrap();
+        assert_eq!(result, Object::list(vec![Object::Void, Object::Void]));
+    }
+
+    #[test]
+    fn test_and() {
+        let mut env = base_env();
+        let result = eval("(list (and) (and 1 2 3) (and 1 #f undefined))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(true 3 false)");
+    }
+
+    #[test]
+    fn test_or() {
+        let mut env = base_env();
+        let result = eval("(list (or) (or #f 2 undefined) (or #f #f))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(false 2 false)");
+    }
+
+    #[test]
+    fn test_cond_else_not_last() {
+        let mut env = base_env();
+        let result = eval("(cond (else 1) (#t 2))", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_cond_invalid_clause() {
+        let mut env = base_env();
+        let result = eval("(cond 1)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_case_invalid_clause() {
+        let mut env = base_env();
+        let result = eval("(case 1 (1 2))", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_else_outside_cond() {
+        let mut env = base_env();
+        let result = eval("(else 1)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_conditionals_in_tail_position() {
+        with_small_stack(|| {
+            let mut env = base_env();
+            let program = "
+                (define (count-down n)
+                  (cond ((= n 0) 'done)
+                        (else (and #t (or #f (when #t (case 1 ((1) (count-down (- n 1))))))))))
+                (count-down 10000)
+            ";
+            let result = eval(program, &mut env).unwrap();
+            assert_eq!(result, Object::Symbol("done".to_string()));
+        });
+    }
+
+    #[test]
+    fn test_rest_param() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define (head-and-rest a . rest) (list a rest))
+                (list (head-and-rest 1 2 3) (head-and-rest 1)))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "((1 (2 3)) (1 ()))");
+    }
+
+    #[test]
+    fn test_lambda_taking_all_args() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define all (lambda args (length args)))
+                (list (all) (all 1 2 3)))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(0 3)");
+    }
+
+    #[test]
+    fn test_optional_param() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define (greet name (greeting \"Hello\")) (+ greeting \", \" name))
+                (list (greet \"Bob\") (greet \"Bob\" \"Hi\")))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Object::list(vec![
+                Object::String("Hello, Bob".to_string()),
+                Object::String("Hi, Bob".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_optional_param_defaults_see_earlier_params() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define (scale x (factor 2) (offset (* x factor))) (+ (* x factor) offset))
+                (list (scale 3) (scale 3 1) (scale 3 1 0)))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(12 6 3)");
+    }
+
+    #[test]
+    fn test_macro_rest_param() {
+        let mut env = base_env();
+        let program = "
+            (begin
+                (define-macro (my-list . items) `(list ,@items))
+                (my-list 1 2))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(1 2)");
+    }
+
+    #[test]
+    fn test_required_param_after_optional_param() {
+        let mut env = base_env();
+        let result = eval("(lambda ((a 1) b) a)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_rest_param_not_a_symbol() {
+        let mut env = base_env();
+        let result = eval("(lambda (a . 1) a)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_param_after_rest_param() {
+        let mut env = base_env();
+        let result = eval("(lambda (a . b c) a)", &mut env);
+        assert!(matches!(result, Err(LispError::Parse(_))));
+    }
+
+    #[test]
+    fn test_missing_rest_param() {
+        let mut env = base_env();
+        let result = eval("(lambda (a .) a)", &mut env);
+        assert!(matches!(result, Err(LispError::Parse(_))));
+    }
+
+    /// Evaluates `program`, which must fail with an arity error, and returns
+    /// the name of the procedure, the arity it expects and the number of
+    /// arguments it got.
+    fn arity_error(program: &str, env: &mut Rc<RefCell<Env>>) -> (String, Arity, usize) {
+        match eval(program, env) {
+            Err(LispError::Arity {
+                name,
+                expected,
+                found,
+                ..
+            }) => (name, expected, found),
+            result => panic!("{}: unexpected {:?}", program, result),
+        }
+    }
+
+    #[test]
+    fn test_lambda_too_few_args() {
+        let mut env = base_env();
+        let program = "(begin (define (add a b) (+ a b)) (add 1))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("add".to_string(), Arity::Exact(2), 1)
+        );
+        let message = eval(program, &mut env).unwrap_err().to_string();
+        assert!(
+            message.contains("add expects 2 argument(s), got 1"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_lambda_too_many_args() {
+        let mut env = base_env();
+        let program = "(begin (define (add a b) (+ a b)) (add 1 2 3))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("add".to_string(), Arity::Exact(2), 3)
+        );
+    }
+
+    #[test]
+    fn test_lambda_with_rest_param_too_few_args() {
+        let mut env = base_env();
+        let program = "(begin (define (f a (b 1) . c) (list a b c)) (f))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("f".to_string(), Arity::AtLeast(1), 0)
+        );
+    }
+
+    #[test]
+    fn test_arity_error_names_defined_lambda() {
+        let mut env = base_env();
+        let program = "(begin (define g (lambda (x) x)) (g))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("g".to_string(), Arity::Exact(1), 0)
+        );
+    }
+
+    #[test]
+    fn test_arity_error_names_lambda_where_defined() {
+        let mut env = base_env();
+        let program = "(begin (define (add a b) (+ a b)) (define h add) (h 1))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("add".to_string(), Arity::Exact(2), 1)
+        );
+    }
+
+    #[test]
+    fn test_arity_error_in_map() {
+        let mut env = base_env();
+        let program = "(begin (define (add a b) (+ a b)) (map add (list 1)))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("add".to_string(), Arity::Exact(2), 1)
+        );
+    }
+
+    #[test]
+    fn test_arity_error_in_filter() {
+        let mut env = base_env();
+        let program = "(begin (define (add a b) (+ a b)) (filter add (list 1)))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("add".to_string(), Arity::Exact(2), 1)
+        );
+    }
+
+    #[test]
+    fn test_arity_error_in_reduce() {
+        let mut env = base_env();
+        let program = "(begin (define (g x) x) (reduce g (list 1 2)))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("g".to_string(), Arity::Exact(1), 2)
+        );
+    }
+
+    #[test]
+    fn test_arity_error_in_named_let() {
+        let mut env = base_env();
+        let program = "(let loop ((i 0)) (loop))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("loop".to_string(), Arity::Exact(1), 0)
+        );
+    }
+
+    #[test]
+    fn test_arity_error_in_anonymous_lambda() {
+        let mut env = base_env();
+        let program = "((lambda (x (y 1)) (+ x y)))";
+        assert_eq!(
+            arity_error(program, &mut env),
+            ("lambda".to_string(), Arity::Range(1, 2), 0)
+        );
+    }
+
+    #[test]
+    fn test_lambda_body_sequence() {
+        let mut env = base_env();
+        let result = eval("((lambda (x) (+ x 1) (* x x)) 5)", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(25));
+    }
+
+    #[test]
+    fn test_lambda_single_expression_body() {
+        let mut env = base_env();
+        let result = eval("((lambda (x) x) 7)", &mut env).unwrap();
+        assert_eq!(result, Object::Integer(7));
+    }
+
+    #[test]
+    fn test_internal_defines() {
+        let mut env = base_env();
+        let program = "
+            (define calls 0)
+            (define (square x)
+              (set! calls (+ calls 1))
+              (* x x))
+            (define (hypot-squared a b)
+              (define a2 (square a))
+              (define (b2) (square b))
+              (+ a2 (b2)))
+            (list (hypot-squared 3 4) calls)
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(25 2)");
+        // Internal definitions are local to each call.
+        for name in ["a2", "b2"] {
+            let result = eval(name, &mut env);
+            assert!(matches!(result, Err(LispError::UnboundSymbol { .. })));
+        }
+    }
+
+    #[test]
+    fn test_internal_defines_in_macro_body() {
+        let mut env = base_env();
+        let program = "
+            (define-macro (swap-args f a b)
+              (define args (list b a))
+              `(,f ,@args))
+            (swap-args - 1 10)
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(9));
+        let result = eval("args", &mut env);
+        assert!(matches!(result, Err(LispError::UnboundSymbol { .. })));
+    }
+
+    #[test]
+    fn test_internal_defines_in_named_let() {
+        let mut env = base_env();
+        let program = "(let loop ((i 0)) (define j (+ i 1)) (if (= j 5) j (loop j)))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(5));
+    }
+
+    #[test]
+    fn test_lambda_without_body() {
+        let mut env = base_env();
+        let result = eval("(lambda (x))", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_define_with_extra_value() {
+        let mut env = base_env();
+        let result = eval("(define x 1 2)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_lambda_body_in_tail_position() {
+        with_small_stack(|| {
+            let mut env = base_env();
+            let program = "
+                (define (count n)
+                  (define next (- n 1))
+                  (if (= n 0) 'done (count next)))
+                (count 10000)
+            ";
+            let result = eval(program, &mut env).unwrap();
+            assert_eq!(result, Object::Symbol("done".to_string()));
+            assert!(matches!(
+                eval("next", &mut env),
+                Err(LispError::UnboundSymbol { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_set() {
+        let mut env = base_env();
+        let program = "
+            (define (make-counter)
+              (let ((n 0))
+                (lambda () (begin (set! n (+ n 1)) n))))
+            (define a (make-counter))
+            (define b (make-counter))
+            (a)
+            (a)
+            (b)
+            (define x 1)
+            (define (f) (set! x 2))
+            (f)
+        ";
+        eval(program, &mut env).unwrap();
+        assert_eq!(
+            eval("(list (a) (b) x)", &mut env).unwrap().to_string(),
+            "(3 2 2)"
+        );
+        assert!(matches!(
+            eval("(set! y 1)", &mut env),
+            Err(LispError::UnboundSymbol { .. })
+        ));
+        assert!(matches!(
+            eval("(set! 1 1)", &mut env),
+            Err(LispError::Syntax { .. })
+        ));
+    }
+
+    #[test]
+    fn test_syntax_rules() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define r 5.0)
-                (define pi 3.14)
-                (* pi (* r r))
-            )";
+            (define-syntax my-or
+              (syntax-rules ()
+                ((_) #f)
+                ((_ e) e)
+                ((_ e r ...) (let ((tmp e)) (if tmp tmp (my-or r ...))))))
+            (list (my-or) (my-or #f #t #f))
+        ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Float((3.14 * 5.0 * 5.0) as f64));
+        assert_eq!(result.to_string(), "(false true)");
     }
 
     #[test]
-    fn test_range_no_stride() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
-        let program = "(range 0 11)";
+    fn test_syntax_rules_ellipsis() {
+        let mut env = base_env();
+        let program = "
+            (define-syntax sums
+              (syntax-rules () ((_ (a b) ...) (list (+ a b) ...))))
+            (list (sums (1 2) (3 4)) (sums))
+        ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(
-            result,
-            Object::ListData(vec![
-                Object::Integer(0),
-                Object::Integer(1),
-                Object::Integer(2),
-                Object::Integer(3),
-                Object::Integer(4),
-                Object::Integer(5),
-                Object::Integer(6),
-                Object::Integer(7),
-                Object::Integer(8),
-                Object::Integer(9),
-                Object::Integer(10)
-            ])
-        );
+        assert_eq!(result.to_string(), "((3 7) ())");
+        let result = eval("(macroexpand '(sums (1 2)))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(list (+ 1 2))");
     }
 
     #[test]
-    fn test_range_with_stride() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
-        let program = "(range 0 10 3)";
+    fn test_syntax_rules_nested_ellipsis() {
+        let mut env = base_env();
+        let program = "
+            (define-syntax flatten
+              (syntax-rules () ((_ (a ...) ...) '(a ... ...))))
+            (flatten (1 2) () (3))
+        ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(
-            result,
-            Object::ListData(vec![
-                Object::Integer(0),
-                Object::Integer(3),
-                Object::Integer(6),
-                Object::Integer(9),
-            ])
-        );
+        assert_eq!(result.to_string(), "(1 2 3)");
     }
 
     #[test]
-    fn test_area_of_a_circle() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_pattern_after_ellipsis() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define r 10)
-                (define pi 314)
-                (* pi (* r r))
-            )";
+            (define-syntax last
+              (syntax-rules () ((_ a ... z) z)))
+            (last 1 2 3)
+        ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((314 * 10 * 10) as i64));
+        assert_eq!(result, Object::Integer(3));
+        let result = eval("(last)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
     }
 
     #[test]
-    fn test_sqr_function() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_literals() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define sqr (lambda (r) (* r r))) 
-                (sqr 10)
-            )";
+            (define-syntax for
+              (syntax-rules (in)
+                ((_ x in items body) (map (lambda (x) body) items))))
+            (for x in (list 1 2 3) (* x x))
+        ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((10 * 10) as i64));
+        assert_eq!(result.to_string(), "(1 4 9)");
+        let result = eval("(for x on (list 1) x)", &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
     }
 
     #[test]
-    fn test_map() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_ellipsis_in_quasiquote() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define sqr (lambda (r) (* r r)))
-                (define l (list 1 2 3 4 5))
-                (map sqr l)
-            )
+            (define-syntax tagged
+              (syntax-rules () ((_ x ...) `(tag ,(+ x ...) (x ...) ,@(list x ...)))))
+            (tagged 1 2)
         ";
-
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(
-            result,
-            Object::ListData(vec![
-                Object::Integer(1),
-                Object::Integer(4),
-                Object::Integer(9),
-                Object::Integer(16),
-                Object::Integer(25)
-            ])
-        );
+        assert_eq!(result.to_string(), "(tag 3 (1 2) 1 2)");
     }
 
     #[test]
-    fn test_filter() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_as_value() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define odd (lambda (v) (= 1 (% v 2))))
-                (define l (list 1 2 3 4 5))
-                (filter odd l)
-            )
+            (define-syntax first
+              (syntax-rules () ((_ l) (car l))))
+            (map first (list 1))
         ";
+        let result = eval(program, &mut env);
+        assert!(matches!(result, Err(LispError::Syntax { .. })));
+    }
 
+    #[test]
+    fn test_syntax_rules_hygiene() {
+        let mut env = base_env();
+        let program = "
+            (define-syntax my-or
+              (syntax-rules ()
+                ((_) #f)
+                ((_ e) e)
+                ((_ e r ...) (let ((tmp e)) (if tmp tmp (my-or r ...))))))
+            (define (f tmp) (my-or #f tmp))
+            (define tmp 5)
+            (list (my-or #f tmp) (f 7))
+        ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(
-            result,
-            Object::ListData(vec![
-                Object::Integer(1),
-                Object::Integer(3),
-                Object::Integer(5)
-            ])
-        );
+        assert_eq!(result.to_string(), "(5 7)");
     }
 
     #[test]
-    fn test_reduce() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_free_identifier() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define odd (lambda (v) (= 1 (% v 2))))
-                (define l (list 1 2 3 4 5))
-                (reduce (lambda (x y) (| x y)) (map odd l))
-            )
+            (define-syntax first
+              (syntax-rules () ((_ l) (car l))))
+            (let ((car cdr)) (first (list 1 2)))
         ";
-
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Bool(true));
+        assert_eq!(result, Object::Integer(1));
     }
 
     #[test]
-    fn test_fibonaci() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_set_free_identifier() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define fib (lambda (n) 
-                    (if (< n 2) 1 
-                        (+ (fib (- n 1)) 
-                            (fib (- n 2))))))
-                (fib 10)
-            )
+            (define-syntax count!
+              (syntax-rules () ((_) (set! counter (+ counter 1)))))
+            (define counter 0)
+            (list (let ((counter 10)) (count!) counter) counter)
         ";
-
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((89) as i64));
+        assert_eq!(result.to_string(), "(10 1)");
     }
 
     #[test]
-    fn test_factorial() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_shadowed_else() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define fact (lambda (n) (if (< n 1) 1 (* n (fact (- n 1))))))
-                (fact 5)
-            )
+            (define-syntax pick
+              (syntax-rules () ((_ x) (cond (x 1) (else 2)))))
+            (let ((else #f)) (pick #f))
         ";
-
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((120) as i64));
+        assert_eq!(result, Object::Integer(2));
     }
 
     #[test]
-    fn test_circle_area_no_lambda() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_call_in_changing_scope() {
+        let mut env = base_env();
         let program = "
             (begin
-                (define pi 314)
-                (define r 10)
-                (define (sqr r) (* r r))
-                (define (area r) (* pi (sqr r)))
-                (area r)
-            )
+                (define-syntax first
+                  (syntax-rules () ((_ l) (car l))))
+                (define (g shadow)
+                  (if shadow (define car cdr) #f)
+                  (first (list 1 2)))
+                (list (g #f) (g #t)))
         ";
-
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((314 * 10 * 10) as i64));
+        assert_eq!(result.to_string(), "(1 1)");
     }
 
     #[test]
-    fn test_circle_area_function() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_syntax_rules_dotted_patterns() {
+        let mut env = base_env();
         let program = "
             (begin
-                (define pi 314)
-                (define r 10)
-                (define sqr (lambda (r) (* r r)))
-                (define area (lambda (r) (* pi (sqr r))))
-                (area r)
-            )
+                (define-syntax rest-of
+                  (syntax-rules () ((_ a . b) (quote b))))
+                (define-syntax call
+                  (syntax-rules () ((_ f . args) (f . args))))
+                (define-syntax firsts
+                  (syntax-rules () ((_ (a . b) ...) (list a ...))))
+                (define-syntax all
+                  (syntax-rules () ((_ a ... . r) '((a ...) r))))
+                (list (rest-of 1 2 3) (rest-of 1) (call + 1 2) (firsts (1 2) (3)) (all 1 2)))
         ";
-
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((314 * 10 * 10) as i64));
+        assert_eq!(result.to_string(), "((2 3) () 3 (1 3) ((1 2) ()))");
     }
 
     #[test]
-    fn test_tail_recursion() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_let_syntax() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define sum-n 
-                   (lambda (n a) 
-                      (if (= n 0) a 
-                          (sum-n (- n 1) (+ n a)))))
-                (sum-n 500 0)
-            )
+            (let-syntax ((double (syntax-rules () ((_ x) (* x 2)))))
+              (double 21))
         ";
+        assert_eq!(eval(program, &mut env).unwrap(), Object::Integer(42));
+        assert!(matches!(
+            eval("(double 1)", &mut env),
+            Err(LispError::UnboundSymbol { .. })
+        ));
+    }
+
+    #[test]
+    fn test_gensym() {
+        let mut env = base_env();
+        let a = eval("(gensym)", &mut env).unwrap();
+        let b = eval("(gensym \"tmp\")", &mut env).unwrap();
+        assert_ne!(a, b);
+        assert!(matches!(&b, Object::Symbol(s) if s.starts_with("#:tmp")));
+    }
 
+    #[test]
+    fn test_cons() {
+        let mut env = base_env();
+        let program = "(list (cons 1 2) (cons 1 (cons 2 '())) (cons 1 (cons 2 3)))";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((125250) as i64));
+        assert_eq!(result.to_string(), "((1 . 2) (1 2) (1 2 . 3))");
     }
 
     #[test]
-    fn test_tail_recursive_factorial() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_dotted_literals() {
+        let mut env = base_env();
+        let program = "(list '() '(1 . 2) '(1 . (2 3)) '(1 2 . ()) (cdr '(1 . 2)))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(() (1 . 2) (1 2 3) (1 2) 2)");
+    }
+
+    #[test]
+    fn test_dotted_quasiquote() {
+        let mut env = base_env();
+        let program = "(list `(1 . ,(+ 1 1)) `(0 ,@(list 1 2) . 3))";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "((1 . 2) (0 1 2 . 3))");
+    }
+
+    #[test]
+    fn test_macroexpand_dotted_quote() {
+        let mut env = base_env();
+        let result = eval("(macroexpand ''(a . b))", &mut env).unwrap();
+        assert_eq!(result.to_string(), "(quote (a . b))");
+    }
+
+    #[test]
+    fn test_set_car_shared_tail() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define fact 
-                    (lambda (n a) 
-                      (if (= n 1) a 
-                        (fact (- n 1) (* n a)))))
-                        
-                (fact 10 1)
-            )
+            (define shared (list 2 3))
+            (define a (cons 1 shared))
+            (define b (cons 0 shared))
+            (set-car! shared 20)
+            (list a b)
         ";
-
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((3628800) as i64));
+        assert_eq!(result.to_string(), "((1 20 3) (0 20 3))");
     }
 
     #[test]
-    fn test_closure1() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_set_cdr_improper_list() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define add-n 
-                   (lambda (n) 
-                      (lambda (a) (+ n a))))
-                (define add-5 (add-n 5))
-                (add-5 10)
-            )
+            (define c (list 1 2))
+            (set-cdr! (cdr c) 3)
+            c
         ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(1 2 . 3)");
+        let result = eval("(length c)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+    }
 
+    #[test]
+    fn test_pair_predicates() {
+        let mut env = base_env();
+        let program = "(list (null? '()) (null? '(1)) (pair? '(1)) (pair? '()) (length '()))";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((15) as i64));
+        assert_eq!(result.to_string(), "(true false true false 0)");
     }
 
     #[test]
-    fn test_tail_recursive_fibonnaci() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_pair_accessors_on_non_pairs() {
+        let mut env = base_env();
+        let result = eval("(car '())", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+        let result = eval("(cdr 1)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+        let result = eval("(set-car! '() 1)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+    }
+
+    #[test]
+    fn test_circular_cdr_printing() {
+        let mut env = base_env();
         let program = "
-            (begin
-                (define fib
-                  (lambda (n a b) 
-                     (if (= n 0) a 
-                       (if (= n 1) b 
-                          (fib (- n 1) b (+ a b))))))
-                  
-                (fib 10 0 1)
-            )
+            (define c (list 1 2))
+            (set-cdr! (cdr c) c)
+            (list (car (cdr (cdr c))) c (cdr c))
         ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(1 (1 2 ...) (2 1 ...))");
+    }
 
+    #[test]
+    fn test_circular_car_printing() {
+        let mut env = base_env();
+        let program = "
+            (define e (list 1))
+            (set-car! e e)
+            (list e e)
+        ";
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((55) as i64));
+        assert_eq!(result.to_string(), "((...) (...))");
+        assert_eq!(eval("e", &mut env).unwrap().to_string(), "(...)");
     }
 
     #[test]
-    fn test_inline_lambda() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+    fn test_circular_list_operations() {
+        let mut env = base_env();
+        eval("(define c (list 1 2)) (set-cdr! (cdr c) c)", &mut env).unwrap();
+        let result = eval("(length c)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+        let result = eval("(map car c)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+        let result = eval("(filter car c)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+        let result = eval("(reduce + c)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+        let result = eval("`(0 ,@c)", &mut env);
+        assert!(matches!(result, Err(LispError::Type { .. })));
+    }
+
+    #[test]
+    fn test_circular_list_equality() {
+        let mut env = base_env();
         let program = "
-        (begin
-            ((lambda (x y) (+ x y)) 10 20)
-        )
+            (define c (list 1 2))
+            (set-cdr! (cdr c) c)
+            (define d (list 1 2))
+            (set-cdr! (cdr d) d)
         ";
+        eval(program, &mut env).unwrap();
+        let (c, d) = (eval("c", &mut env).unwrap(), eval("d", &mut env).unwrap());
+        assert_eq!(c, d);
+        assert_ne!(c, eval("(cdr d)", &mut env).unwrap());
+        assert_ne!(c, eval("(list 1 2)", &mut env).unwrap());
+    }
 
-        let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((30) as i64));
+    #[test]
+    fn test_long_lists() {
+        with_small_stack(|| {
+            let mut env = base_env();
+            let program = "
+                (define (sum items acc)
+                  (if (null? items) acc (sum (cdr items) (+ acc (car items)))))
+                (sum (range 0 10000) 0)
+            ";
+            let result = eval(program, &mut env).unwrap();
+            assert_eq!(result, Object::Integer(49995000));
+            assert_eq!(
+                eval("(range 0 10000)", &mut env).unwrap(),
+                eval("(map (lambda (x) x) (range 0 10000))", &mut env).unwrap()
+            );
+        });
     }
 
     #[test]
     fn test_car() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin
             (car (list 1 2 3))
@@ -978,12 +2814,12 @@ mod tests {
         ";
 
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((1) as i64));
+        assert_eq!(result, Object::Integer(1));
     }
 
     #[test]
     fn test_cdr() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin
             (cdr (list 1 2 3))
@@ -993,13 +2829,13 @@ mod tests {
         let result = eval(program, &mut env).unwrap();
         assert_eq!(
             result,
-            Object::ListData(vec![Object::Integer(2), Object::Integer(3),])
+            Object::list(vec![Object::Integer(2), Object::Integer(3),])
         );
     }
 
     #[test]
     fn test_length() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin
             (length (list 1 2 3))
@@ -1007,12 +2843,12 @@ mod tests {
         ";
 
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((3) as i64));
+        assert_eq!(result, Object::Integer(3));
     }
 
     #[test]
     fn test_sum_list_of_integers() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin
             (define sum-list 
@@ -1029,7 +2865,7 @@ mod tests {
 
     #[test]
     fn test_function_application() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin
             (define (double value) 
@@ -1042,12 +2878,12 @@ mod tests {
         ";
 
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((20) as i64));
+        assert_eq!(result, Object::Integer(20));
     }
 
     #[test]
     fn test_begin_scope_test() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin
             (define a 10)
@@ -1065,7 +2901,7 @@ mod tests {
         let result = eval(program, &mut env).unwrap();
         assert_eq!(
             result,
-            Object::ListData(vec![
+            Object::list(vec![
                 Object::Integer(20),
                 Object::Integer(30),
                 Object::Integer(40),
@@ -1075,7 +2911,7 @@ mod tests {
 
     #[test]
     fn test_begin_scope_test_2() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin 
             (define x 10)
@@ -1088,12 +2924,12 @@ mod tests {
         ";
 
         let result = eval(program, &mut env).unwrap();
-        assert_eq!(result, Object::Integer((10) as i64));
+        assert_eq!(result, Object::Integer(10));
     }
 
     #[test]
     fn test_let_1() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin
             (let ((a 10) (b 20))
@@ -1105,13 +2941,13 @@ mod tests {
         let result = eval(program, &mut env).unwrap();
         assert_eq!(
             result,
-            Object::ListData(vec![Object::Integer(10), Object::Integer(20),])
+            Object::list(vec![Object::Integer(10), Object::Integer(20),])
         );
     }
 
     #[test]
     fn test_let_2() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
         (begin
             (define a 100)
@@ -1128,7 +2964,7 @@ mod tests {
 
     #[test]
     fn test_let_3() {
-        let mut env = Rc::new(RefCell::new(Env::new()));
+        let mut env = base_env();
         let program = "
             (let ((x 2) (y 3))
                 (let ((x 7)
@@ -1139,4 +2975,80 @@ mod tests {
         let result = eval(program, &mut env).unwrap();
         assert_eq!(result, Object::Integer(35));
     }
+
+    #[test]
+    fn test_let_star() {
+        let mut env = base_env();
+        let program = "
+            (let ((x 2) (y 3))
+                (let* ((x 7)
+                       (z (+ x y)))
+                    (* z x)))
+        ";
+
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result, Object::Integer(70));
+    }
+
+    #[test]
+    fn test_letrec() {
+        with_small_stack(|| {
+            let mut env = base_env();
+            let program = "
+                (letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+                         (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+                    (list (even? 10000) (odd? 7)))
+            ";
+
+            let result = eval(program, &mut env).unwrap();
+            assert_eq!(result.to_string(), "(true true)");
+            assert!(eval("(letrec ((x 1)) (y))", &mut env).is_err());
+        });
+    }
+
+    #[test]
+    fn test_named_let() {
+        with_small_stack(|| {
+            let mut env = base_env();
+            let program = "
+                (let loop ((i 0) (acc 0))
+                    (if (= i 10000) acc (loop (+ i 1) (+ acc i))))
+            ";
+
+            let result = eval(program, &mut env).unwrap();
+            assert_eq!(result, Object::Integer(49995000));
+            let result = eval("(let loop ((i 3)) i)", &mut env).unwrap();
+            assert_eq!(result, Object::Integer(3));
+        });
+    }
+
+    #[test]
+    fn test_do() {
+        with_small_stack(|| {
+            let mut env = base_env();
+            let program = "
+                (do ((i 0 (+ i 1))
+                     (acc 0 (+ acc i)))
+                    ((= i 10000) acc))
+            ";
+            let result = eval(program, &mut env).unwrap();
+            assert_eq!(result, Object::Integer(49995000));
+        });
+
+        let mut env = base_env();
+
+        let program = "
+            (define procs (list))
+            (do ((i 0 (+ i 1)))
+                ((= i 3))
+                (set! procs (list (lambda () (+ i 0)) procs)))
+            (map (lambda (p) (p)) (list (car procs) (car (car (cdr procs)))))
+        ";
+        let result = eval(program, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(2 1)");
+        assert!(matches!(
+            eval("(do ((i 0)) ())", &mut env),
+            Err(LispError::Syntax { .. })
+        ));
+    }
 }
@@ -0,0 +1,399 @@
+use crate::builtins::*;
+use crate::convert::*;
+use crate::env::*;
+use crate::error::*;
+use crate::eval::*;
+use crate::object::*;
+use crate::span::*;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// A Lisp interpreter that owns its global environment, so definitions made
+/// by one call to `eval` are visible to the next.
+#[derive(Debug)]
+pub struct Interpreter {
+    env: Rc<RefCell<Env>>,
+}
+
+/// A handle to a Lisp procedure that can be called from Rust with arguments
+/// of type `A` (a tuple) and a result of type `R`.
+#[derive(Debug, Clone)]
+pub struct Function<A, R> {
+    func: Object,
+    marker: PhantomData<fn(A) -> R>,
+}
+
+impl<A: IntoLispArgs, R: FromLisp> Function<A, R> {
+    pub fn call(&self, args: A) -> Result<R, LispError> {
+        let result = apply(&self.func, args.into_lisp_args())?;
+        R::from_lisp(&result)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter { env: base_env() }
+    }
+
+    /// Evaluates `program` in the global environment and returns its value.
+    pub fn eval(&mut self, program: &str) -> Result<Object, LispError> {
+        eval(program, &mut self.env)
+    }
+
+    /// Like `eval`, but errors refer to the program by `name`, typically the
+    /// path of the file it was read from.
+    pub fn eval_source(&mut self, name: &str, program: &str) -> Result<Object, LispError> {
+        eval_source(&Source::new(name, program), &mut self.env)
+    }
+
+    /// Binds `name` to `value` in the global environment.
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        self.env.borrow_mut().set(name, value);
+    }
+
+    /// Removes the global binding of `name`, e.g. to withhold a builtin from
+    /// untrusted code. Returns the value it was bound to.
+    pub fn remove_global(&mut self, name: &str) -> Option<Object> {
+        self.env.borrow_mut().remove(name)
+    }
+
+    /// Looks up `name` in the global environment.
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.env.borrow().get(name)
+    }
+
+    /// Looks up the global procedure `name` so that it can be called from
+    /// Rust.
+    pub fn function<A, R>(&self, name: &str) -> Result<Function<A, R>, LispError>
+    where
+        A: IntoLispArgs,
+        R: FromLisp,
+    {
+        match self.get_global(name) {
+            Some(func @ (Object::Lambda(_, _, _) | Object::NativeFn(_, _, _))) => Ok(Function {
+                func,
+                marker: PhantomData,
+            }),
+            Some(obj) => Err(LispError::type_error("procedure", &obj)),
+            None => Err(LispError::unbound(name)),
+        }
+    }
+
+    /// Exposes a Rust closure to Lisp code as the global function `name`.
+    /// The closure receives the evaluated arguments of each call, after they
+    /// have been checked against `arity`.
+    pub fn register_fn<F>(&mut self, name: &str, arity: Arity, func: F)
+    where
+        F: Fn(&[Object]) -> Result<Object, LispError> + 'static,
+    {
+        let native = Object::NativeFn(name.to_string(), arity, NativeFunc::new(func));
+        self.set_global(name, native);
+    }
+
+    /// Like `register_fn`, but the arguments are converted into `A` (a tuple
+    /// with one element per argument) and the result is converted back from
+    /// `R`.
+    pub fn register_typed_fn<A, R, F>(&mut self, name: &str, arity: Arity, func: F)
+    where
+        A: FromLisp,
+        R: IntoLisp,
+        F: Fn(A) -> Result<R, LispError> + 'static,
+    {
+        self.register_fn(name, arity, move |args| {
+            let args = A::from_lisp(&Object::list(args.to_vec()))?;
+            func(args).map(IntoLisp::into_lisp)
+        });
+    }
+
+    /// Binds `name` to a special form implemented in Rust, which receives its
+    /// arguments unevaluated.
+    pub fn register_special_form(&mut self, name: &str, form: impl SpecialForm + 'static) {
+        let form = Object::SpecialForm(name.to_string(), NativeForm::new(form));
+        self.set_global(name, form);
+    }
+
+    /// Discards every global definition, restoring the builtins.
+    pub fn reset(&mut self) {
+        self.env = base_env();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_definitions_persist() {
+        let mut interp = Interpreter::new();
+        interp.eval("(define (sqr x) (* x x))").unwrap();
+        let result = interp.eval("(sqr 7)").unwrap();
+        assert_eq!(result, Object::Integer(49));
+    }
+
+    #[test]
+    fn test_multiple_top_level_forms() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval("42").unwrap(), Object::Integer(42));
+        assert_eq!(interp.eval("").unwrap(), Object::Void);
+        let program = "
+            (define a 1)
+            (define b 2)
+            (+ a b)
+        ";
+        assert_eq!(interp.eval(program).unwrap(), Object::Integer(3));
+        assert_eq!(interp.eval("a b").unwrap(), Object::Integer(2));
+        assert!(matches!(interp.eval("(1 2)"), Err(LispError::Type { .. })));
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut interp = Interpreter::new();
+        interp.set_global("answer", Object::Integer(42));
+        let result = interp.eval("(+ answer 1)").unwrap();
+        assert_eq!(result, Object::Integer(43));
+
+        interp.eval("(define greeting \"hello\")").unwrap();
+        assert_eq!(
+            interp.get_global("greeting"),
+            Some(Object::String("hello".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut interp = Interpreter::new();
+        interp.register_fn("double", Arity::Exact(1), |args| match &args[0] {
+            Object::Integer(n) => Ok(Object::Integer(n * 2)),
+            obj => Err(LispError::type_error("integer", obj)),
+        });
+        assert_eq!(interp.eval("(double 21)").unwrap(), Object::Integer(42));
+        assert_eq!(
+            interp.eval("(map double (list 1 2 3))").unwrap(),
+            Object::list(vec![
+                Object::Integer(2),
+                Object::Integer(4),
+                Object::Integer(6)
+            ])
+        );
+        assert!(interp.eval("(double 1 2)").is_err());
+        assert!(interp.eval("(double \"a\")").is_err());
+    }
+
+    #[test]
+    fn test_native_fn_in_filter_and_reduce() {
+        let mut interp = Interpreter::new();
+        interp.register_fn("even?", Arity::Exact(1), |args| match &args[0] {
+            Object::Integer(n) => Ok(Object::Bool(n % 2 == 0)),
+            obj => Err(LispError::type_error("integer", obj)),
+        });
+        interp.register_fn("sum", Arity::AtLeast(0), |args| {
+            let mut total = 0;
+            for arg in args {
+                match arg {
+                    Object::Integer(n) => total += n,
+                    obj => return Err(LispError::type_error("integer", obj)),
+                }
+            }
+            Ok(Object::Integer(total))
+        });
+        let result = interp
+            .eval("(reduce sum (filter even? (list 1 2 3 4 5 6)))")
+            .unwrap();
+        assert_eq!(result, Object::Integer(12));
+        assert_eq!(interp.eval("(sum 1 2 3 4)").unwrap(), Object::Integer(10));
+    }
+
+    #[test]
+    fn test_remove_global() {
+        let mut interp = Interpreter::new();
+        assert!(interp.remove_global("print").is_some());
+        assert!(matches!(
+            interp.eval("(print 1)"),
+            Err(LispError::UnboundSymbol { .. })
+        ));
+        interp.reset();
+        assert!(interp.get_global("print").is_some());
+    }
+
+    /// `(with-retry n body)` evaluates `body` up to `n` times, until it
+    /// succeeds.
+    struct WithRetry;
+
+    impl SpecialForm for WithRetry {
+        fn eval(&self, form: &[Object], env: &Rc<RefCell<Env>>) -> Result<Step, LispError> {
+            if form.len() != 3 {
+                return Err(LispError::arity(
+                    "with-retry",
+                    Arity::Exact(2),
+                    form.len() - 1,
+                ));
+            }
+            let attempts = match eval_form(&form[1], env)? {
+                Object::Integer(n) if n > 0 => n,
+                obj => return Err(LispError::type_error("positive integer", &obj)),
+            };
+            for _ in 1..attempts {
+                if let Ok(value) = eval_form(&form[2], env) {
+                    return Ok(Step::Value(value));
+                }
+            }
+            // The last attempt is evaluated in tail position.
+            Ok(Step::Eval(form[2].clone(), env.clone()))
+        }
+    }
+
+    #[test]
+    fn test_register_special_form() {
+        let mut interp = Interpreter::new();
+        let calls = Rc::new(RefCell::new(0));
+        let counter = calls.clone();
+        interp.register_fn("flaky", Arity::Exact(0), move |_| {
+            *counter.borrow_mut() += 1;
+            match *counter.borrow() {
+                n if n < 3 => Err(LispError::user("try again")),
+                n => Ok(Object::Integer(n)),
+            }
+        });
+        interp.register_special_form("with-retry", WithRetry);
+
+        assert_eq!(
+            interp.eval("(with-retry (+ 2 3) (flaky))").unwrap(),
+            Object::Integer(3)
+        );
+        assert_eq!(*calls.borrow(), 3);
+
+        *calls.borrow_mut() = 0;
+        assert!(matches!(
+            interp.eval("(with-retry 2 (flaky))"),
+            Err(LispError::User { .. })
+        ));
+        assert!(matches!(
+            interp.eval("(with-retry 2)"),
+            Err(LispError::Arity { .. })
+        ));
+    }
+
+    #[test]
+    fn test_special_form_tail_position() {
+        let mut interp = Interpreter::new();
+        interp.register_special_form("with-retry", WithRetry);
+        interp
+            .eval("(define (count n) (if (= n 0) 0 (with-retry 1 (count (- n 1)))))")
+            .unwrap();
+        assert_eq!(interp.eval("(count 100000)").unwrap(), Object::Integer(0));
+    }
+
+    #[test]
+    fn test_register_typed_fn() {
+        let mut interp = Interpreter::new();
+        interp.register_typed_fn(
+            "hypot",
+            Arity::Exact(2),
+            |(x, y): (f64, f64)| Ok(x.hypot(y)),
+        );
+        interp.register_typed_fn("repeat", Arity::Exact(2), |(s, n): (String, i64)| {
+            Ok(vec![s; n as usize])
+        });
+        assert_eq!(interp.eval("(hypot 3 4.0)").unwrap(), Object::Float(5.0));
+        assert_eq!(
+            interp.eval("(repeat \"a\" 2)").unwrap(),
+            vec!["a", "a"].into_lisp()
+        );
+        assert!(interp.eval("(hypot 3 \"4\")").is_err());
+    }
+
+    #[test]
+    fn test_call_lambda_from_rust() {
+        let mut interp = Interpreter::new();
+        interp.eval("(define (score x y) (/ (+ x y) 2.0))").unwrap();
+        let score = interp.function::<(i64, i64), f64>("score").unwrap();
+        assert_eq!(score.call((3, 4)), Ok(3.5));
+        assert_eq!(score.call((10, 0)), Ok(5.0));
+        assert!(interp.function::<(i64,), i64>("missing").is_err());
+    }
+
+    #[test]
+    fn test_call_tail_recursive_lambda_from_rust() {
+        let mut interp = Interpreter::new();
+        interp
+            .eval(
+                "(define sum-n
+                    (lambda (n a)
+                      (if (= n 0) a
+                          (sum-n (- n 1) (+ n a)))))",
+            )
+            .unwrap();
+        let sum_n = interp.function::<(i64, i64), i64>("sum-n").unwrap();
+        assert_eq!(sum_n.call((100000, 0)), Ok(5000050000));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let mut interp = Interpreter::new();
+        assert!(matches!(
+            interp.eval("(+ x 1)"),
+            Err(LispError::UnboundSymbol { .. })
+        ));
+        assert!(matches!(
+            interp.eval("(+ 1 \"a\")"),
+            Err(LispError::Type { .. })
+        ));
+        assert!(matches!(
+            interp.eval("(car (list 1) (list 2))"),
+            Err(LispError::Arity { found: 2, .. })
+        ));
+        assert!(matches!(
+            interp.eval("(/ 1 0)"),
+            Err(LispError::DivisionByZero { .. })
+        ));
+        assert!(matches!(
+            interp.eval("(* 9223372036854775807 2)"),
+            Err(LispError::LimitExceeded { .. })
+        ));
+        assert!(matches!(interp.eval(")"), Err(LispError::Parse(_))));
+    }
+
+    #[test]
+    fn test_error_location() {
+        let mut interp = Interpreter::new();
+        let program = "(begin
+  (define (f x)
+    (+ x \"a\"))
+  (f 1))";
+        let err = interp.eval_source("test.lisp", program).unwrap_err();
+        let span = err.span().unwrap();
+        assert_eq!((span.line, span.column), (3, 5));
+        assert_eq!(
+            err.to_string(),
+            "Type error: expected number, found string a
+ --> test.lisp:3:5
+  |
+3 |     (+ x \"a\"))
+  |     ^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let mut interp = Interpreter::new();
+        let err = interp.eval_source("test.lisp", "\n  )").unwrap_err();
+        let span = err.span().unwrap();
+        assert_eq!((span.line, span.column), (2, 3));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut interp = Interpreter::new();
+        interp.eval("(define x 10)").unwrap();
+        interp.reset();
+        assert_eq!(interp.get_global("x"), None);
+        assert!(interp.eval("(+ x 1)").is_err());
+    }
+}
@@ -1,7 +1,8 @@
-use std::collections::HashSet;
+use crate::span::*;
 use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
 use std::str::Chars;
-use std::{fmt, vec};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -11,156 +12,569 @@ pub enum Token {
     RParen,
     Float(f64),
     String(String),
-    BinaryOp(String),
-    Keyword(String),
+    /// `#;`, which comments out the datum that follows it.
+    DatumComment,
+    /// `'`, short for `(quote datum)`.
+    Quote,
+    /// `` ` ``, short for `(quasiquote datum)`.
+    Quasiquote,
+    /// `,`, short for `(unquote datum)`.
+    Unquote,
+    /// `,@`, short for `(unquote-splicing datum)`.
+    UnquoteSplicing,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TokenError {
     err: String,
+    span: Span,
+    unterminated: Option<Unterminated>,
+}
+
+/// What was left open when the input ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unterminated {
+    String,
+    BlockComment,
+}
+
+impl TokenError {
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Returns what was left open if the error is due to the input ending
+    /// too early, in which case more input could fix it.
+    pub fn unterminated(&self) -> Option<Unterminated> {
+        self.unterminated
+    }
 }
 
 impl Error for TokenError {}
 
 impl fmt::Display for TokenError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Tokenization error: {}", self.err)
+        write!(f, "Tokenization error: {}\n{}", self.err, self.span)
     }
 }
 
 struct Tokenizer<'a> {
+    source: &'a Rc<Source>,
     input: Chars<'a>,
     current_char: Option<char>,
-    keywords: HashSet<&'a str>,
-    binary_ops: HashSet<char>,
+    offset: usize,
+    line: usize,
+    column: usize,
 }
 
 impl<'a> Tokenizer<'a> {
-    pub fn new(input: &'a str) -> Self {
-        let mut chars = input.chars();
+    pub fn new(source: &'a Rc<Source>) -> Self {
+        let mut chars = source.text.chars();
         let current_char = chars.next();
 
-        let keywords: HashSet<&str> = vec![
-            "define", "list", "print", "lambda", "map", "filter", "reduce", "range", "car", "cdr",
-            "length", "null?", "begin", "let", "if",
-        ]
-        .into_iter()
-        .collect::<HashSet<&str>>();
-
-        let binary_ops: HashSet<char> = vec!['+', '-', '*', '/', '%', '<', '>', '=', '|', '&']
-            .into_iter()
-            .collect::<HashSet<char>>();
-
         Tokenizer {
+            source,
             input: chars,
             current_char,
-            keywords,
-            binary_ops,
+            offset: 0,
+            line: 1,
+            column: 1,
         }
     }
 
     fn advance(&mut self) -> Option<char> {
+        if let Some(c) = self.current_char {
+            self.offset += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
         self.current_char = self.input.next();
         self.current_char
     }
 
-    fn eat_whitespace(&mut self) {
+    /// Returns the span from the position `(start, line, column)` up to the
+    /// current character.
+    fn span_from(&self, start: usize, line: usize, column: usize) -> Span {
+        Span {
+            source: self.source.clone(),
+            start,
+            end: self.offset,
+            line,
+            column,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input.clone().next()
+    }
+
+    /// Whether the `+` or `-` at the current position starts a number rather
+    /// than an operator, as in `-5`, `+.5` or `-inf.0`.
+    fn starts_signed_number(&self) -> bool {
+        let rest = self
+            .input
+            .clone()
+            .take_while(|&c| !is_delimiter(c))
+            .collect::<String>();
+        rest.starts_with(|c: char| c.is_ascii_digit())
+            || (rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+            || rest == "inf.0"
+            || rest == "nan.0"
+    }
+
+    /// Skips whitespace, `;` line comments and `#| ... |#` block comments.
+    fn eat_whitespace(&mut self) -> Result<(), TokenError> {
         while let Some(c) = self.current_char {
-            if !c.is_whitespace() {
-                break;
+            match c {
+                c if c.is_whitespace() => {
+                    self.advance();
+                }
+                ';' => {
+                    while !matches!(self.current_char, None | Some('\n')) {
+                        self.advance();
+                    }
+                }
+                '#' if self.peek() == Some('|') => self.eat_block_comment()?,
+                _ => break,
             }
-            self.advance();
         }
+        Ok(())
     }
 
-    fn read_symbol(&mut self) -> String {
-        let mut symbol = String::new();
+    /// Skips a block comment, which may contain nested block comments.
+    fn eat_block_comment(&mut self) -> Result<(), TokenError> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let mut depth = 0;
         while let Some(c) = self.current_char {
-            if c.is_whitespace() || c == '(' || c == ')' || c == '\'' {
-                break;
+            match (c, self.peek()) {
+                ('#', Some('|')) => {
+                    depth += 1;
+                    self.advance();
+                }
+                ('|', Some('#')) => {
+                    depth -= 1;
+                    self.advance();
+                    if depth == 0 {
+                        self.advance();
+                        return Ok(());
+                    }
+                }
+                _ => {}
             }
-            symbol.push(c);
             self.advance();
         }
-        symbol
+        let mut err = self.error(
+            "Unterminated block comment".to_string(),
+            start,
+            line,
+            column,
+        );
+        err.unterminated = Some(Unterminated::BlockComment);
+        Err(err)
+    }
+
+    fn error(&self, err: String, start: usize, line: usize, column: usize) -> TokenError {
+        TokenError {
+            err,
+            span: self.span_from(start, line, column),
+            unterminated: None,
+        }
     }
 
-    fn read_number(&mut self) -> String {
-        let mut number = String::new();
+    /// Reads characters up to the next delimiter.
+    fn read_atom(&mut self) -> String {
+        let mut atom = String::new();
         while let Some(c) = self.current_char {
-            if !c.is_numeric() && c != '.' {
+            if is_delimiter(c) {
                 break;
             }
-            number.push(c);
+            atom.push(c);
             self.advance();
         }
-        number
+        atom
     }
 
-    fn read_string(&mut self) -> String {
+    fn read_symbol(&mut self) -> Result<String, TokenError> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let symbol = self.read_atom();
+        let mut offset = start;
+        for (i, c) in symbol.chars().enumerate() {
+            if !is_symbol_char(c) {
+                // Point at the offending character rather than the whole atom.
+                let mut err = self.error(
+                    format!("Unexpected character {:?} in symbol {}", c, symbol),
+                    offset,
+                    line,
+                    column + i,
+                );
+                err.span.end = offset + c.len_utf8();
+                return Err(err);
+            }
+            offset += c.len_utf8();
+        }
+        Ok(symbol)
+    }
+
+    fn read_number(&mut self) -> Result<Token, TokenError> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let number = self.read_atom();
+        parse_number(&number).map_err(|err| self.error(err, start, line, column))
+    }
+
+    fn read_string(&mut self) -> Result<String, TokenError> {
+        let (start, line, column) = (self.offset, self.line, self.column);
         let mut string = String::new();
         self.advance(); // Skip the opening quote
         while let Some(c) = self.current_char {
-            if c == '"' {
-                self.advance(); // Skip the closing quote
+            match c {
+                '"' => {
+                    self.advance(); // Skip the closing quote
+                    return Ok(string);
+                }
+                '\\' if self.peek().is_none() => break,
+                '\\' => {
+                    if let Some(c) = self.read_escape()? {
+                        string.push(c);
+                    }
+                }
+                _ => {
+                    string.push(c);
+                    self.advance();
+                }
+            }
+        }
+        let mut err = self.error("Unterminated string".to_string(), start, line, column);
+        err.unterminated = Some(Unterminated::String);
+        Err(err)
+    }
+
+    /// Reads an escape sequence starting at a backslash. Returns `None` for a
+    /// line continuation, a backslash at the end of a line, which skips the
+    /// line break and the leading whitespace of the next line.
+    fn read_escape(&mut self) -> Result<Option<char>, TokenError> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let escape = self.advance();
+        let c = match escape {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\n') => {
+                while matches!(self.advance(), Some(c) if c.is_whitespace()) {}
+                return Ok(None);
+            }
+            Some('x') => {
+                let digits = self.read_escape_digits(';')?;
+                self.code_point(&digits, start, line, column)?
+            }
+            Some('u') => {
+                if self.advance() != Some('{') {
+                    return Err(self.escape_error("Expected { after \\u", start, line, column));
+                }
+                let digits = self.read_escape_digits('}')?;
+                self.code_point(&digits, start, line, column)?
+            }
+            _ => return Err(self.escape_error("Unknown escape sequence", start, line, column)),
+        };
+        self.advance();
+        Ok(Some(c))
+    }
+
+    /// Reads hex digits up to `terminator`, leaving the terminator as the
+    /// current character.
+    fn read_escape_digits(&mut self, terminator: char) -> Result<String, TokenError> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let mut digits = String::new();
+        while let Some(c) = self.advance() {
+            if c == terminator {
+                return Ok(digits);
+            }
+            if !c.is_ascii_hexdigit() {
                 break;
             }
+            digits.push(c);
+        }
+        Err(self.escape_error(
+            &format!("Expected hex digits terminated by {}", terminator),
+            start,
+            line,
+            column,
+        ))
+    }
+
+    fn code_point(
+        &self,
+        digits: &str,
+        start: usize,
+        line: usize,
+        column: usize,
+    ) -> Result<char, TokenError> {
+        u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.escape_error("Invalid code point", start, line, column))
+    }
+
+    fn escape_error(&self, err: &str, start: usize, line: usize, column: usize) -> TokenError {
+        let mut err = self.error(err.to_string(), start, line, column);
+        err.span.end = self.offset + self.current_char.map_or(0, char::len_utf8);
+        err
+    }
+
+    /// Reads a raw string, `#r"..."`, in which backslashes have no special
+    /// meaning. Any number of `#`s may follow the `r` and must then follow
+    /// the closing quote, so that `#r#"say "hi""#` can contain quotes.
+    fn read_raw_string(&mut self) -> Result<String, TokenError> {
+        let (start, line, column) = (self.offset, self.line, self.column);
+        self.advance(); // Skip the #
+        let mut hashes = 0;
+        while self.advance() == Some('#') {
+            hashes += 1;
+        }
+        if self.current_char != Some('"') {
+            return Err(self.error(
+                "Expected \" to start raw string".to_string(),
+                start,
+                line,
+                column,
+            ));
+        }
+        let closing = format!("\"{}", "#".repeat(hashes));
+        let mut string = String::new();
+        while let Some(c) = self.advance() {
             string.push(c);
-            self.advance();
+            if string.ends_with(&closing) {
+                string.truncate(string.len() - closing.len());
+                self.advance();
+                return Ok(string);
+            }
         }
-        string
+        let mut err = self.error("Unterminated raw string".to_string(), start, line, column);
+        err.unterminated = Some(Unterminated::String);
+        Err(err)
     }
 
-    pub fn next_token(&mut self) -> Option<Token> {
-        self.eat_whitespace();
+    pub fn next_token(&mut self) -> Result<Option<(Token, Span)>, TokenError> {
+        self.eat_whitespace()?;
+
+        let (start, line, column) = (self.offset, self.line, self.column);
+        let token = match self.read_token(start, line, column)? {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        Ok(Some((token, self.span_from(start, line, column))))
+    }
 
-        match self.current_char? {
+    fn read_token(
+        &mut self,
+        start: usize,
+        line: usize,
+        column: usize,
+    ) -> Result<Option<Token>, TokenError> {
+        let c = match self.current_char {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let token = match c {
             '(' => {
                 self.advance();
-                Some(Token::LParen)
+                Token::LParen
             }
             ')' => {
                 self.advance();
-                Some(Token::RParen)
-            }
-            '"' => Some(Token::String(self.read_string())),
-            c if c.is_numeric() => {
-                let val = self.read_number();
-                if val.contains('.') {
-                    Some(Token::Float(val.parse().unwrap()))
-                } else {
-                    Some(Token::Integer(val.parse().unwrap()))
+                Token::RParen
+            }
+            '"' => Token::String(self.read_string()?),
+            '\'' | '`' | ',' => {
+                self.advance();
+                match c {
+                    '\'' => Token::Quote,
+                    '`' => Token::Quasiquote,
+                    _ if self.current_char == Some('@') => {
+                        self.advance();
+                        Token::UnquoteSplicing
+                    }
+                    _ => Token::Unquote,
                 }
             }
-            c if c.is_alphabetic() || self.binary_ops.contains(&c) => {
-                let sym = self.read_symbol();
-                if self.keywords.contains(sym.as_str()) {
-                    Some(Token::Keyword(sym))
-                } else if self.binary_ops.contains(&sym.chars().next().unwrap()) {
-                    Some(Token::BinaryOp(sym))
-                } else {
-                    Some(Token::Symbol(sym))
+            c if c.is_ascii_digit() => self.read_number()?,
+            '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => self.read_number()?,
+            '+' | '-' if self.starts_signed_number() => self.read_number()?,
+            // The dot of rest parameters, as in `(lambda (a . rest) ...)`.
+            '.' if self.peek().is_none_or(is_delimiter) => {
+                self.advance();
+                Token::Symbol(".".to_string())
+            }
+            // The ellipsis of `syntax-rules` patterns.
+            '.' if self.input.clone().take(2).eq("..".chars()) => {
+                Token::Symbol(self.read_symbol()?)
+            }
+            '#' if self.peek() == Some('r') => Token::String(self.read_raw_string()?),
+            '#' if self.peek() == Some(';') => {
+                self.advance();
+                self.advance();
+                Token::DatumComment
+            }
+            '#' if matches!(
+                self.peek(),
+                Some('x' | 'X' | 'b' | 'B' | 'o' | 'O' | 'd' | 'D')
+            ) =>
+            {
+                self.read_number()?
+            }
+            '#' => {
+                let sym = self.read_symbol()?;
+                match sym.as_str() {
+                    "#t" | "#f" | "#nil" => Token::Symbol(sym),
+                    _ => {
+                        return Err(self.error(
+                            format!("Unknown syntax {}", sym),
+                            start,
+                            line,
+                            column,
+                        ))
+                    }
                 }
             }
-            _ => None,
+            c if is_symbol_start(c) => Token::Symbol(self.read_symbol()?),
+            c => {
+                self.advance();
+                return Err(self.error(
+                    format!("Unexpected character {:?}", c),
+                    start,
+                    line,
+                    column,
+                ));
+            }
+        };
+        Ok(Some(token))
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()\"'`,;".contains(c)
+}
+
+fn is_symbol_start(c: char) -> bool {
+    c.is_alphabetic() || "!$%&*/:<=>?^_~+-|".contains(c)
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || "!$%&*/:<=>?^_~+-.@#|".contains(c)
+}
+
+/// Parses a numeric literal: an optionally signed decimal integer or float
+/// (with optional exponent), an integer with a `#x`, `#b`, `#o` or `#d` radix
+/// prefix, or one of `+inf.0`, `-inf.0` and `+nan.0`. Digits may be separated
+/// by single underscores, as in `1_000_000`.
+fn parse_number(number: &str) -> Result<Token, String> {
+    let invalid = || format!("Invalid number {}", number);
+    let out_of_range = || format!("Integer literal {} is out of range", number);
+
+    let (radix, rest) = match number.get(..2).map(|p| p.to_ascii_lowercase()).as_deref() {
+        Some("#x") => (16, &number[2..]),
+        Some("#b") => (2, &number[2..]),
+        Some("#o") => (8, &number[2..]),
+        Some("#d") => (10, &number[2..]),
+        _ => (10, number),
+    };
+    let (sign, digits) = match rest.chars().next() {
+        Some(c @ ('+' | '-')) => (Some(c), &rest[1..]),
+        _ => (None, rest),
+    };
+
+    if radix == 10 && sign.is_some() && rest.len() == number.len() {
+        match digits {
+            "inf.0" if sign == Some('-') => return Ok(Token::Float(f64::NEG_INFINITY)),
+            "inf.0" => return Ok(Token::Float(f64::INFINITY)),
+            "nan.0" => return Ok(Token::Float(f64::NAN)),
+            _ => {}
+        }
+    }
+
+    let digits = strip_separators(digits, radix).ok_or_else(invalid)?;
+    let signed = format!("{}{}", sign.unwrap_or('+'), digits);
+    if digits.chars().all(|c| c.is_digit(radix)) {
+        if digits.is_empty() {
+            return Err(invalid());
         }
+        return i64::from_str_radix(&signed, radix)
+            .map(Token::Integer)
+            .map_err(|_| out_of_range());
+    }
+
+    // Anything else must be a decimal float. The characters are checked
+    // first, since Rust's float parser also accepts `inf` and `NaN`.
+    let is_float_char = |c: char| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-');
+    if radix != 10
+        || rest.len() != number.len()
+        || !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        || !digits.chars().all(is_float_char)
+    {
+        return Err(invalid());
+    }
+    match signed.parse::<f64>() {
+        Ok(f) if f.is_finite() => Ok(Token::Float(f)),
+        Ok(_) => Err(format!("Float literal {} is out of range", number)),
+        Err(_) => Err(invalid()),
     }
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, TokenError> {
-    let mut tokenizer = Tokenizer::new(input);
-    let mut tokens = Vec::new();
-    while let Some(token) = tokenizer.next_token() {
-        tokens.push(token);
+/// Removes the underscores from `digits`, provided each one sits between two
+/// digits of the given radix.
+fn strip_separators(digits: &str, radix: u32) -> Option<String> {
+    let chars = digits.chars().collect::<Vec<_>>();
+    let mut stripped = String::with_capacity(digits.len());
+    for (i, &c) in chars.iter().enumerate() {
+        if c != '_' {
+            stripped.push(c);
+            continue;
+        }
+        let before = i.checked_sub(1).map(|j| chars[j]);
+        let after = chars.get(i + 1).copied();
+        if !before.is_some_and(|c| c.is_digit(radix)) || !after.is_some_and(|c| c.is_digit(radix)) {
+            return None;
+        }
     }
+    Some(stripped)
+}
 
-    Ok(tokens)
+/// Tokenizes `source`, pairing every token with its location.
+pub fn tokenize_source(source: &Rc<Source>) -> Result<Vec<(Token, Span)>, TokenError> {
+    match tokenize_partial(source) {
+        (tokens, None) => Ok(tokens),
+        (_, Some(err)) => Err(err),
+    }
+}
+
+/// Like `tokenize_source`, but also returns the tokens read before an error.
+pub fn tokenize_partial(source: &Rc<Source>) -> (Vec<(Token, Span)>, Option<TokenError>) {
+    let mut tokenizer = Tokenizer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        match tokenizer.next_token() {
+            Ok(Some(token)) => tokens.push(token),
+            Ok(None) => return (tokens, None),
+            Err(err) => return (tokens, Some(err)),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn tokenize(input: &str) -> Result<Vec<Token>, TokenError> {
+        let tokens = tokenize_source(&Source::new("<input>", input))?;
+        Ok(tokens.into_iter().map(|(token, _)| token).collect())
+    }
+
     #[test]
     fn test_add() {
         let tokens = tokenize("(+ 1 2)").unwrap_or(vec![]);
@@ -168,7 +582,7 @@ mod tests {
             tokens,
             vec![
                 Token::LParen,
-                Token::BinaryOp("+".to_string()),
+                Token::Symbol("+".to_string()),
                 Token::Integer(1),
                 Token::Integer(2),
                 Token::RParen,
@@ -176,6 +590,254 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_comparison_operators() {
+        let tokens = tokenize("(<= >= != =)").unwrap();
+        let ops = ["<=", ">=", "!=", "="].map(|op| Token::Symbol(op.to_string()));
+        assert_eq!(tokens[1..5], ops);
+    }
+
+    #[test]
+    fn test_quote_tokens() {
+        assert_eq!(
+            tokenize("'a`(b ,c ,@d)").unwrap(),
+            vec![
+                Token::Quote,
+                Token::Symbol("a".to_string()),
+                Token::Quasiquote,
+                Token::LParen,
+                Token::Symbol("b".to_string()),
+                Token::Unquote,
+                Token::Symbol("c".to_string()),
+                Token::UnquoteSplicing,
+                Token::Symbol("d".to_string()),
+                Token::RParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let source = Source::new("test.lisp", "(define\n  pi 3.14)");
+        let tokens = tokenize_source(&source).unwrap();
+        let positions = tokens
+            .iter()
+            .map(|(_, span)| (span.start, span.end, span.line, span.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            vec![
+                (0, 1, 1, 1),
+                (1, 7, 1, 2),
+                (10, 12, 2, 3),
+                (13, 17, 2, 6),
+                (17, 18, 2, 10)
+            ]
+        );
+    }
+
+    fn tokenize_err(input: &str) -> TokenError {
+        tokenize_source(&Source::new("<input>", input)).unwrap_err()
+    }
+
+    #[test]
+    fn test_unexpected_character() {
+        let err = tokenize_err("(+ 1 [2])");
+        assert_eq!((err.span().start, err.span().column), (5, 6));
+        assert_eq!(
+            err.to_string(),
+            "Tokenization error: Unexpected character '['
+ --> <input>:1:6
+  |
+1 | (+ 1 [2])
+  |      ^"
+        );
+        let err = tokenize_err("(define a{b 1)");
+        assert_eq!((err.span().start, err.span().end), (9, 10));
+    }
+
+    #[test]
+    fn test_extended_symbols() {
+        let tokens = tokenize("(_x set-car! $y ~z a->b #t ... . .5)").unwrap();
+        assert_eq!(
+            tokens[1..10],
+            [
+                Token::Symbol("_x".to_string()),
+                Token::Symbol("set-car!".to_string()),
+                Token::Symbol("$y".to_string()),
+                Token::Symbol("~z".to_string()),
+                Token::Symbol("a->b".to_string()),
+                Token::Symbol("#t".to_string()),
+                Token::Symbol("...".to_string()),
+                Token::Symbol(".".to_string()),
+                Token::Float(0.5),
+            ]
+        );
+        assert!(tokenize("#foo").is_err());
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let err = tokenize_err("(print \"hello)");
+        assert_eq!(err.span().start, 7);
+        assert!(err.to_string().contains("Unterminated string"));
+    }
+
+    #[test]
+    fn test_invalid_numbers() {
+        assert!(tokenize_err("1.2.3")
+            .to_string()
+            .contains("Invalid number 1.2.3"));
+        assert!(tokenize_err("12abc")
+            .to_string()
+            .contains("Invalid number 12abc"));
+        assert!(tokenize_err("123456789012345678901234567890")
+            .to_string()
+            .contains("out of range"));
+        assert_eq!(
+            tokenize("(1.5 9223372036854775807)").unwrap()[1..3],
+            [Token::Float(1.5), Token::Integer(i64::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_signed_numbers() {
+        assert_eq!(
+            tokenize("(- -5 +3.2 -.5 -x - +)").unwrap()[1..7],
+            [
+                Token::Symbol("-".to_string()),
+                Token::Integer(-5),
+                Token::Float(3.2),
+                Token::Float(-0.5),
+                Token::Symbol("-x".to_string()),
+                Token::Symbol("-".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize("-9223372036854775808").unwrap(),
+            [Token::Integer(i64::MIN)]
+        );
+        assert!(tokenize("-5x").is_err());
+    }
+
+    #[test]
+    fn test_exponents_and_separators() {
+        assert_eq!(
+            tokenize("1e-9 2.5E+3 1e3 1_000_000 0.000_1").unwrap(),
+            [
+                Token::Float(1e-9),
+                Token::Float(2500.0),
+                Token::Float(1000.0),
+                Token::Integer(1_000_000),
+                Token::Float(0.0001),
+            ]
+        );
+        assert!(tokenize("1e").is_err());
+        assert!(tokenize("1__0").is_err());
+        assert!(tokenize("1_").is_err());
+        assert!(tokenize("1e400")
+            .unwrap_err()
+            .to_string()
+            .contains("Float literal 1e400 is out of range"));
+    }
+
+    #[test]
+    fn test_radix_prefixes() {
+        assert_eq!(
+            tokenize("#x1F #xff_ff #b1010 #o17 #d42 #x-A").unwrap(),
+            [
+                Token::Integer(31),
+                Token::Integer(0xffff),
+                Token::Integer(10),
+                Token::Integer(15),
+                Token::Integer(42),
+                Token::Integer(-10),
+            ]
+        );
+        assert!(tokenize("#b102").is_err());
+        assert!(tokenize("#x").is_err());
+        assert!(tokenize("#x1.5").is_err());
+    }
+
+    #[test]
+    fn test_special_floats() {
+        let tokens = tokenize("+inf.0 -inf.0 +nan.0").unwrap();
+        assert_eq!(
+            tokens[..2],
+            [Token::Float(f64::INFINITY), Token::Float(f64::NEG_INFINITY)]
+        );
+        assert!(matches!(tokens[2], Token::Float(f) if f.is_nan()));
+        assert!(tokenize("inf.0").unwrap() == [Token::Symbol("inf.0".to_string())]);
+    }
+
+    #[test]
+    fn test_comments() {
+        let program = "
+            ; the answer
+            (+ 40 #| two, #| nested |# |# 2) ; trailing
+            #;(ignored) x;comment
+        ";
+        assert_eq!(
+            tokenize(program).unwrap(),
+            vec![
+                Token::LParen,
+                Token::Symbol("+".to_string()),
+                Token::Integer(40),
+                Token::Integer(2),
+                Token::RParen,
+                Token::DatumComment,
+                Token::LParen,
+                Token::Symbol("ignored".to_string()),
+                Token::RParen,
+                Token::Symbol("x".to_string()),
+            ]
+        );
+        assert!(tokenize_err("(a #| b #| c |# d)")
+            .to_string()
+            .contains("Unterminated block comment"));
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let tokens = tokenize(r#""a\"b\\c\nd\te\rf\0g\x41;\u{1F600}""#).unwrap();
+        assert_eq!(
+            tokens,
+            vec![Token::String("a\"b\\c\nd\te\rf\0gA\u{1F600}".to_string())]
+        );
+        let tokens = tokenize("\"one \\\n     two\nthree\"").unwrap();
+        assert_eq!(tokens, vec![Token::String("one two\nthree".to_string())]);
+    }
+
+    #[test]
+    fn test_bad_escapes() {
+        let err = tokenize_err(r#"(print "a\qb")"#);
+        assert_eq!((err.span().start, err.span().end), (9, 11));
+        assert!(err.to_string().contains("Unknown escape sequence"));
+        assert!(tokenize_err(r#""\x41""#)
+            .to_string()
+            .contains("terminated by ;"));
+        assert!(tokenize_err(r#""\u{110000}""#)
+            .to_string()
+            .contains("Invalid code point"));
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let tokens = tokenize(r###"(#r"C:\dir\n" #r#"say "hi""#)"###).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LParen,
+                Token::String(r"C:\dir\n".to_string()),
+                Token::String(r#"say "hi""#.to_string()),
+                Token::RParen,
+            ]
+        );
+        assert!(tokenize_err(r##"#r#"abc""##)
+            .to_string()
+            .contains("Unterminated raw string"));
+    }
+
     #[test]
     fn test_area_of_a_circle() {
         let program = "
@@ -191,20 +853,20 @@ mod tests {
             vec![
                 Token::LParen,
                 Token::LParen,
-                Token::Keyword("define".to_string()),
+                Token::Symbol("define".to_string()),
                 Token::Symbol("r".to_string()),
                 Token::Integer(10),
                 Token::RParen,
                 Token::LParen,
-                Token::Keyword("define".to_string()),
+                Token::Symbol("define".to_string()),
                 Token::Symbol("pi".to_string()),
                 Token::Integer(314),
                 Token::RParen,
                 Token::LParen,
-                Token::BinaryOp("*".to_string()),
+                Token::Symbol("*".to_string()),
                 Token::Symbol("pi".to_string()),
                 Token::LParen,
-                Token::BinaryOp("*".to_string()),
+                Token::Symbol("*".to_string()),
                 Token::Symbol("r".to_string()),
                 Token::Symbol("r".to_string()),
                 Token::RParen,
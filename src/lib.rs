@@ -1,32 +1,42 @@
+mod builtins;
+mod convert;
 mod env;
+mod error;
 mod eval;
+mod interpreter;
 mod lexer;
 mod object;
 mod parser;
+mod span;
+mod syntax_rules;
 
-use object::Object;
-use std::cell::RefCell;
-use std::rc::Rc;
+pub use convert::{FromLisp, IntoLisp, IntoLispArgs};
+pub use env::Env;
+pub use error::LispError;
+pub use eval::{eval_form, Step};
+pub use interpreter::{Function, Interpreter};
+pub use lexer::{TokenError, Unterminated};
+pub use object::{Arity, NativeForm, NativeFunc, Object, Pair, Params, SpecialForm, Written};
+pub use parser::{parse_status, ParseError, ParseStatus};
+pub use span::{Source, Span};
 
 pub fn lisp_rs_eval(input: &str) -> String {
-    let mut env = Rc::new(RefCell::new(env::Env::new()));
-    let val = eval::eval(input, &mut env);
+    let val = Interpreter::new().eval(input);
     match val {
         Ok(Object::Void) => "".to_string(),
         Ok(Object::Integer(n)) => n.to_string(),
         Ok(Object::Bool(b)) => b.to_string(),
         Ok(Object::Symbol(s)) => s.to_string(),
         Ok(Object::Lambda(params, body, _)) => {
-            let mut res = "Lambda(".to_string();
-            for param in params {
-                res.push_str(&format!("{} ", param));
-            }
-            res.push(')');
+            let mut res = format!("Lambda({})", params);
             for expr in (*body).iter() {
                 res.push_str(&format!(" {}", expr));
             }
             res
         }
+        Ok(Object::NativeFn(name, _, _)) => format!("NativeFn({})", name),
+        Ok(Object::SpecialForm(name, _)) => format!("SpecialForm({})", name),
+        Ok(obj @ (Object::Macro(_, _, _) | Object::SyntaxRules(_))) => obj.to_string(),
         Ok(Object::List(list)) => {
             let mut res = "(".to_string();
             for (i, obj) in (*list).iter().enumerate() {
@@ -38,20 +48,8 @@ pub fn lisp_rs_eval(input: &str) -> String {
             res.push(')');
             res
         }
-        Ok(Object::ListData(list)) => {
-            let mut res = "(".to_string();
-            for (i, obj) in list.iter().enumerate() {
-                if i > 0 {
-                    res.push(' ');
-                }
-                res.push_str(&format!("{}", obj));
-            }
-            res.push(')');
-            res.to_string()
-        }
+        Ok(obj @ (Object::Null | Object::Pair(_))) => obj.to_string(),
         Ok(Object::String(s)) => s.to_string(),
-        Ok(Object::Keyword(s)) => s.to_string(),
-        Ok(Object::BinaryOp(s)) => s.to_string(),
         Ok(Object::Float(n)) => n.to_string(),
         Err(e) => e.to_string(),
     }
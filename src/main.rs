@@ -1,43 +1,55 @@
-mod env;
-mod eval;
-mod lexer;
-mod object;
-mod parser;
-
 use linefeed::{Interface, ReadResult};
-use object::Object;
-use std::cell::RefCell;
-use std::rc::Rc;
+use lisp_rs::{parse_status, Interpreter, Object, ParseStatus, Source};
 
 const PROMPT: &str = "lisp-rs> ";
+const CONTINUATION_PROMPT: &str = "...      ";
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let reader = Interface::new(PROMPT).unwrap();
-    let mut env = Rc::new(RefCell::new(env::Env::new()));
+    let mut interp = Interpreter::new();
 
-    reader.set_prompt(format!("{}", PROMPT).as_ref()).unwrap();
+    reader.set_prompt(PROMPT).unwrap();
 
+    // Lines are collected until they form a complete program.
+    let mut buffer = String::new();
     while let ReadResult::Input(input) = reader.read_line().unwrap() {
-        if input.eq("exit") {
+        if buffer.is_empty() && input.eq("exit") {
             break;
         }
-        let val = eval::eval(input.as_ref(), &mut env)?;
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        }
+        buffer.push_str(&input);
+        let status = parse_status(&Source::new("<repl>", &buffer));
+        if let ParseStatus::Incomplete { .. } = status {
+            reader.set_prompt(CONTINUATION_PROMPT).unwrap();
+            continue;
+        }
+        reader.set_prompt(PROMPT).unwrap();
+        let program = std::mem::take(&mut buffer);
+        if let ParseStatus::Error(e) = status {
+            eprintln!("{}", e);
+            continue;
+        }
+        let val = match interp.eval_source("<repl>", &program) {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
         match val {
             Object::Void => {}
             Object::Integer(n) => println!("{}", n),
             Object::Bool(b) => println!("{}", b),
             Object::Symbol(s) => println!("{}", s),
             Object::Lambda(params, body, _) => {
-                println!("Lambda(");
-                for param in params {
-                    println!("{} ", param);
-                }
-                println!(")");
+                println!("Lambda({})", params);
                 for expr in (*body).iter() {
                     println!(" {}", expr);
                 }
             }
-            _ => println!("{}", val),
+            _ => println!("{}", val.written()),
         }
     }
 
@@ -1,65 +1,464 @@
 use crate::env::*;
+use crate::error::*;
+use crate::eval::Step;
+use crate::syntax_rules::SyntaxRules;
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fmt;
 use std::rc::Rc;
 
+/// Number of arguments a native function accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    Range(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exact(m) => n == m,
+            Arity::AtLeast(m) => n >= m,
+            Arity::Range(lo, hi) => lo <= n && n <= hi,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+            Arity::Range(lo, hi) => write!(f, "{} to {}", lo, hi),
+        }
+    }
+}
+
+/// The parameters of a lambda: `(a b (c default) . rest)` has the required
+/// parameters `a` and `b`, the optional parameter `c`, bound to the value of
+/// `default` when the argument is missing, and the rest parameter `rest`,
+/// bound to the list of the remaining arguments. `name` is the name the
+/// lambda was defined with, if any, for error messages.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Params {
+    pub required: Vec<String>,
+    pub optional: Vec<(String, Object)>,
+    pub rest: Option<String>,
+    pub name: Option<String>,
+}
+
+impl Params {
+    /// The number of arguments accepted by a procedure with these parameters.
+    pub fn arity(&self) -> Arity {
+        let min = self.required.len();
+        match self.rest {
+            Some(_) => Arity::AtLeast(min),
+            None if self.optional.is_empty() => Arity::Exact(min),
+            None => Arity::Range(min, min + self.optional.len()),
+        }
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for param in self.required.iter() {
+            write!(f, "{} ", param)?;
+        }
+        for (param, default) in self.optional.iter() {
+            write!(f, "({} {}) ", param, default.written())?;
+        }
+        if let Some(rest) = &self.rest {
+            write!(f, ". {} ", rest)?;
+        }
+        Ok(())
+    }
+}
+
+/// A Rust closure callable from Lisp. It receives the already evaluated
+/// arguments of the call.
+#[derive(Clone)]
+pub struct NativeFunc(Rc<NativeFnPtr>);
+
+type NativeFnPtr = dyn Fn(&[Object]) -> Result<Object, LispError>;
+
+impl NativeFunc {
+    pub fn new(func: impl Fn(&[Object]) -> Result<Object, LispError> + 'static) -> Self {
+        NativeFunc(Rc::new(func))
+    }
+
+    pub fn call(&self, args: &[Object]) -> Result<Object, LispError> {
+        (self.0)(args)
+    }
+}
+
+impl fmt::Debug for NativeFunc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFunc")
+    }
+}
+
+impl PartialEq for NativeFunc {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Syntax implemented in Rust, such as `define` or `if`. Unlike a native
+/// function, a special form receives its arguments unevaluated and decides
+/// itself which of them to evaluate, see [`eval_form`](crate::eval_form).
+pub trait SpecialForm {
+    /// Evaluates `form`, the whole form being evaluated: `form[0]` is the
+    /// name the special form was invoked by and `form[1..]` are its
+    /// unevaluated arguments. Returning [`Step::Eval`] evaluates a form in
+    /// tail position, without growing the Rust stack.
+    fn eval(&self, form: &[Object], env: &Rc<RefCell<Env>>) -> Result<Step, LispError>;
+}
+
+/// A special form bound in an environment.
+#[derive(Clone)]
+pub struct NativeForm(Rc<dyn SpecialForm>);
+
+impl NativeForm {
+    pub fn new(form: impl SpecialForm + 'static) -> Self {
+        NativeForm(Rc::new(form))
+    }
+
+    pub fn eval(&self, form: &[Object], env: &Rc<RefCell<Env>>) -> Result<Step, LispError> {
+        self.0.eval(form, env)
+    }
+}
+
+impl fmt::Debug for NativeForm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeForm")
+    }
+}
+
+impl PartialEq for NativeForm {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// A cons cell, the building block of lists: `car` is the first element and
+/// `cdr` the rest of the list. Both can be changed in place with `set-car!`
+/// and `set-cdr!`, which every list sharing the pair observes.
+pub struct Pair {
+    car: RefCell<Object>,
+    cdr: RefCell<Object>,
+}
+
+impl Pair {
+    pub fn car(&self) -> Object {
+        self.car.borrow().clone()
+    }
+
+    pub fn cdr(&self) -> Object {
+        self.cdr.borrow().clone()
+    }
+
+    pub fn set_car(&self, car: Object) {
+        *self.car.borrow_mut() = car;
+    }
+
+    pub fn set_cdr(&self, cdr: Object) {
+        *self.cdr.borrow_mut() = cdr;
+    }
+}
+
+// Long lists are chains of pairs, so dropping, comparing and printing them
+// iterate along the cdrs instead of recursing. `set-cdr!` can make a list
+// circular, so comparing and printing also watch for cycles.
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut next = self.cdr.replace(Object::Null);
+        while let Object::Pair(pair) = next {
+            match Rc::try_unwrap(pair) {
+                Ok(pair) => next = pair.cdr.replace(Object::Null),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl PartialEq for Pair {
+    fn eq(&self, other: &Self) -> bool {
+        if self.car() != other.car() {
+            return false;
+        }
+        let (mut left, mut right) = (self.cdr(), other.cdr());
+        // The pairs half as far along both lists. Once both lists are back
+        // to them, the rest of the comparison would repeat itself.
+        let mut slow = (left.clone(), right.clone());
+        let mut steps = 0;
+        loop {
+            match (&left, &right) {
+                (Object::Pair(l), Object::Pair(r)) if Rc::ptr_eq(l, r) => return true,
+                (Object::Pair(l), Object::Pair(r)) => {
+                    if l.car() != r.car() {
+                        return false;
+                    }
+                    let next = (l.cdr(), r.cdr());
+                    (left, right) = next;
+                }
+                _ => return left == right,
+            }
+            steps += 1;
+            if steps % 2 == 0 {
+                if let (Object::Pair(l), Object::Pair(r)) = &slow {
+                    let next = (l.cdr(), r.cdr());
+                    slow = next;
+                }
+            }
+            if same_pair(&slow.0, &left) && same_pair(&slow.1, &right) {
+                return true;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pair")?;
+        fmt_pair(f, self, true)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Object {
     Void,
-    Keyword(String),
-    BinaryOp(String),
     Integer(i64),
     Float(f64),
     Bool(bool),
     String(String),
     Symbol(String),
-    ListData(Vec<Object>),
-    Lambda(Vec<String>, Rc<Vec<Object>>, Rc<RefCell<Env>>),
+    /// The empty list `()`.
+    Null,
+    Pair(Rc<Pair>),
+    Lambda(Rc<Params>, Rc<Vec<Object>>, Rc<RefCell<Env>>),
+    /// A macro defined with `define-macro`: a procedure from the unevaluated
+    /// arguments of a call to the form that replaces the call.
+    Macro(Rc<Params>, Rc<Vec<Object>>, Rc<RefCell<Env>>),
+    /// A macro defined with `syntax-rules`.
+    SyntaxRules(Rc<SyntaxRules>),
+    NativeFn(String, Arity, NativeFunc),
+    SpecialForm(String, NativeForm),
+    /// A list in code, as read by the parser. Quoting it turns it into pairs.
     List(Rc<Vec<Object>>),
 }
 
-impl fmt::Display for Object {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Object {
+    pub fn cons(car: Object, cdr: Object) -> Object {
+        Object::Pair(Rc::new(Pair {
+            car: RefCell::new(car),
+            cdr: RefCell::new(cdr),
+        }))
+    }
+
+    /// Builds the proper list of `items`.
+    pub fn list(items: Vec<Object>) -> Object {
+        Object::list_with_tail(items, Object::Null)
+    }
+
+    /// Builds the list of `items` ending with `tail` instead of the empty
+    /// list, e.g. `(1 2 . 3)`.
+    pub fn list_with_tail(items: Vec<Object>, tail: Object) -> Object {
+        items
+            .into_iter()
+            .rev()
+            .fold(tail, |cdr, car| Object::cons(car, cdr))
+    }
+
+    /// Returns the elements of a proper list, or `None` if the object is not
+    /// one, as for a dotted or circular list.
+    pub fn list_items(&self) -> Option<Vec<Object>> {
+        let mut items = Vec::new();
+        let mut current = self.clone();
+        // Moves at half the speed of `current`, which meets it on a cycle.
+        let mut slow = self.clone();
+        loop {
+            match current {
+                Object::Null => return Some(items),
+                Object::Pair(pair) => {
+                    items.push(pair.car());
+                    current = pair.cdr();
+                }
+                _ => return None,
+            }
+            if items.len() % 2 == 0 {
+                if let Object::Pair(pair) = slow {
+                    slow = pair.cdr();
+                }
+            }
+            if same_pair(&slow, &current) {
+                return None;
+            }
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Void => "void",
+            Object::Integer(_) => "integer",
+            Object::Float(_) => "float",
+            Object::Bool(_) => "boolean",
+            Object::String(_) => "string",
+            Object::Symbol(_) => "symbol",
+            Object::Null | Object::List(_) => "list",
+            Object::Pair(_) => "pair",
+            Object::Lambda(_, _, _) | Object::NativeFn(_, _, _) => "procedure",
+            Object::SpecialForm(_, _) => "special form",
+            Object::Macro(_, _, _) | Object::SyntaxRules(_) => "macro",
+        }
+    }
+
+    /// Returns a wrapper displaying the object in write form, which can be
+    /// read back by the parser: strings are quoted and escaped, booleans are
+    /// printed as `#t` and `#f`.
+    pub fn written(&self) -> Written<'_> {
+        Written(self)
+    }
+
+    fn fmt_with(&self, f: &mut fmt::Formatter, write: bool) -> fmt::Result {
         match self {
+            Object::Void if write => write!(f, "#nil"),
             Object::Void => write!(f, "Void"),
-            Object::Keyword(s) => write!(f, "{}", s),
-            Object::BinaryOp(s) => write!(f, "{}", s),
             Object::Integer(n) => write!(f, "{}", n),
+            Object::Float(n) if write && n.is_nan() => write!(f, "+nan.0"),
+            Object::Float(n) if write && n.is_infinite() => {
+                write!(f, "{}inf.0", if *n > 0.0 { "+" } else { "-" })
+            }
+            Object::Float(n) if write => write!(f, "{:?}", n),
             Object::Float(n) => write!(f, "{}", n),
+            Object::Bool(b) if write => write!(f, "{}", if *b { "#t" } else { "#f" }),
             Object::Bool(b) => write!(f, "{}", b),
             Object::Symbol(s) => write!(f, "{}", s),
+            Object::String(s) if write => write_string(f, s),
             Object::String(s) => write!(f, "{}", s),
-            Object::Lambda(params, body, _env) => {
-                write!(f, "Lambda(")?;
-                for param in params {
-                    write!(f, "{} ", param)?;
-                }
-                write!(f, ")")?;
-                for expr in (*body).iter() {
-                    write!(f, " {}", expr)?;
-                }
-                Ok(())
-            }
-            Object::List(list) => {
-                write!(f, "(")?;
-                for (i, obj) in (*list).iter().enumerate() {
-                    if i > 0 {
-                        write!(f, " ")?;
-                    }
-                    write!(f, "{}", obj)?;
+            Object::Lambda(params, body, _env) => fmt_procedure(f, "Lambda", params, body, write),
+            Object::Macro(params, body, _env) => fmt_procedure(f, "Macro", params, body, write),
+            Object::SyntaxRules(_) => write!(f, "SyntaxRules"),
+            Object::NativeFn(name, _, _) => write!(f, "NativeFn({})", name),
+            Object::SpecialForm(name, _) => write!(f, "SpecialForm({})", name),
+            Object::List(list) => fmt_list(f, list, write),
+            Object::Null => write!(f, "()"),
+            Object::Pair(pair) => fmt_pair(f, pair, write),
+        }
+    }
+}
+
+fn fmt_procedure(
+    f: &mut fmt::Formatter,
+    kind: &str,
+    params: &Params,
+    body: &[Object],
+    write: bool,
+) -> fmt::Result {
+    write!(f, "{}({})", kind, params)?;
+    for expr in body.iter() {
+        write!(f, " ")?;
+        expr.fmt_with(f, write)?;
+    }
+    Ok(())
+}
+
+fn fmt_list(f: &mut fmt::Formatter, list: &[Object], write: bool) -> fmt::Result {
+    write!(f, "(")?;
+    for (i, obj) in list.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        obj.fmt_with(f, write)?;
+    }
+    write!(f, ")")
+}
+
+fn same_pair(a: &Object, b: &Object) -> bool {
+    matches!((a, b), (Object::Pair(a), Object::Pair(b)) if Rc::ptr_eq(a, b))
+}
+
+thread_local! {
+    // The pairs of the lists being printed. Meeting one of them again means
+    // the list is circular, which is printed as `...`.
+    static PRINTING: RefCell<HashSet<*const Pair>> = RefCell::new(HashSet::new());
+}
+
+fn fmt_pair(f: &mut fmt::Formatter, pair: &Pair, write: bool) -> fmt::Result {
+    if !PRINTING.with(|printing| printing.borrow_mut().insert(pair)) {
+        return write!(f, "...");
+    }
+    let mut visited = vec![pair as *const Pair];
+    let result = fmt_pair_items(f, pair, write, &mut visited);
+    PRINTING.with(|printing| {
+        let mut printing = printing.borrow_mut();
+        for pair in visited {
+            printing.remove(&pair);
+        }
+    });
+    result
+}
+
+fn fmt_pair_items(
+    f: &mut fmt::Formatter,
+    pair: &Pair,
+    write: bool,
+    visited: &mut Vec<*const Pair>,
+) -> fmt::Result {
+    write!(f, "(")?;
+    pair.car().fmt_with(f, write)?;
+    let mut rest = pair.cdr();
+    loop {
+        match rest {
+            Object::Null => break,
+            Object::Pair(pair) => {
+                if !PRINTING.with(|printing| printing.borrow_mut().insert(Rc::as_ptr(&pair))) {
+                    write!(f, " ...")?;
+                    break;
                 }
-                write!(f, ")")
+                visited.push(Rc::as_ptr(&pair));
+                write!(f, " ")?;
+                pair.car().fmt_with(f, write)?;
+                rest = pair.cdr();
             }
-            Object::ListData(list) => {
-                write!(f, "(")?;
-                for (i, obj) in list.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, " ")?;
-                    }
-                    write!(f, "{}", obj)?;
-                }
-                write!(f, ")")
+            tail => {
+                write!(f, " . ")?;
+                tail.fmt_with(f, write)?;
+                break;
             }
         }
     }
+    write!(f, ")")
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            '\0' => write!(f, "\\0")?,
+            c if c.is_control() => write!(f, "\\x{:X};", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with(f, false)
+    }
+}
+
+/// Displays an object in write form, see [`Object::written`].
+pub struct Written<'a>(&'a Object);
+
+impl fmt::Display for Written<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_with(f, true)
+    }
 }
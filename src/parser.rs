@@ -1,71 +1,260 @@
+use crate::error::*;
 use crate::lexer::*;
 use crate::object::*;
+use crate::span::*;
 use std::error::Error;
 use std::fmt;
 use std::rc::Rc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ParseError {
     err: String,
+    span: Option<Span>,
+}
+
+impl ParseError {
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Parse error: {}", self.err)
+        write!(f, "Parse error: {}", self.err)?;
+        if let Some(span) = &self.span {
+            write!(f, "\n{}", span)?;
+        }
+        Ok(())
     }
 }
 
 impl Error for ParseError {}
 
-pub fn parse(program: &str) -> Result<Object, ParseError> {
-    let token_result = tokenize(program);
-    if token_result.is_err() {
-        return Err(ParseError {
-            err: format!("{}", token_result.err().unwrap()),
-        });
+pub fn parse(program: &str) -> Result<Vec<Object>, LispError> {
+    parse_program(&Source::new("<input>", program))
+}
+
+/// Parses every top-level form of `source`, recording the location of every
+/// list it contains.
+pub fn parse_program(source: &Rc<Source>) -> Result<Vec<Object>, LispError> {
+    let tokens = tokenize_source(source)?;
+    Ok(parse_tokens(tokens)?)
+}
+
+/// Whether some input is a complete program, see `parse_status`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseStatus {
+    /// The input parsed into these top-level forms.
+    Complete(Vec<Object>),
+    /// The input is valid so far but ends inside a list, a string, a block
+    /// comment or before the datum a quote or `#;` applies to.
+    Incomplete { open_parens: usize, in_string: bool },
+    /// The input is invalid, whatever follows it.
+    Error(LispError),
+}
+
+/// Parses `source` like `parse_program`, but tells apart input that is
+/// invalid from input that only needs more text, e.g. a REPL line like
+/// `(define x`.
+pub fn parse_status(source: &Rc<Source>) -> ParseStatus {
+    let (tokens, err) = tokenize_partial(source);
+
+    let mut open_parens: usize = 0;
+    for (token, span) in tokens.iter() {
+        match token {
+            Token::LParen => open_parens += 1,
+            Token::RParen if open_parens == 0 => {
+                return ParseStatus::Error(LispError::Parse(ParseError {
+                    err: "Unexpected RParen".to_string(),
+                    span: Some(span.clone()),
+                }))
+            }
+            Token::RParen => open_parens -= 1,
+            _ => {}
+        }
     }
 
-    let mut tokens = token_result.unwrap().into_iter().rev().collect::<Vec<_>>();
-    let parsed_list = parse_list(&mut tokens)?;
-    Ok(parsed_list)
+    let unterminated = match err {
+        Some(err) if err.unterminated().is_none() => return ParseStatus::Error(err.into()),
+        err => err.and_then(|err| err.unterminated()),
+    };
+    let dangling_prefix = matches!(
+        tokens.last(),
+        Some((
+            Token::Quote
+                | Token::Quasiquote
+                | Token::Unquote
+                | Token::UnquoteSplicing
+                | Token::DatumComment,
+            _
+        ))
+    );
+    if open_parens > 0 || unterminated.is_some() || dangling_prefix {
+        return ParseStatus::Incomplete {
+            open_parens,
+            in_string: unterminated == Some(Unterminated::String),
+        };
+    }
+    match parse_tokens(tokens) {
+        Ok(forms) => ParseStatus::Complete(forms),
+        Err(err) => ParseStatus::Error(err.into()),
+    }
 }
 
-fn parse_list(tokens: &mut Vec<Token>) -> Result<Object, ParseError> {
-    let token = tokens.pop();
-    if token != Some(Token::LParen) {
-        return Err(ParseError {
-            err: format!("Expected LParen, found {:?}", token),
-        });
+fn parse_tokens(tokens: Vec<(Token, Span)>) -> Result<Vec<Object>, ParseError> {
+    let mut tokens = tokens.into_iter().rev().collect::<Vec<_>>();
+    let mut forms = Vec::new();
+    while let Some((token, span)) = tokens.last().cloned() {
+        match token {
+            Token::DatumComment => {
+                tokens.pop();
+                parse_datum(&mut tokens, &span)?;
+            }
+            Token::RParen => {
+                return Err(ParseError {
+                    err: "Unexpected RParen".to_string(),
+                    span: Some(span),
+                })
+            }
+            _ => forms.push(parse_datum(&mut tokens, &span)?),
+        }
     }
+    Ok(forms)
+}
 
-    let mut list: Vec<Object> = Vec::new();
-    while !tokens.is_empty() {
-        let token = tokens.pop();
-        if token.is_none() {
+fn parse_list(tokens: &mut Vec<(Token, Span)>) -> Result<Object, ParseError> {
+    let start = match tokens.pop() {
+        Some((Token::LParen, span)) => span,
+        token => {
             return Err(ParseError {
-                err: "Did not find enough tokens".to_string(),
-            });
+                err: format!("Expected LParen, found {:?}", token.as_ref().map(|t| &t.0)),
+                span: token.map(|t| t.1),
+            })
         }
-        let t = token.unwrap();
+    };
+
+    let mut list: Vec<Object> = Vec::new();
+    let mut dot = None;
+    let mut end = None;
+    while let Some((t, span)) = tokens.pop() {
         match t {
-            Token::Keyword(k) => list.push(Object::Keyword(k)),
-            Token::BinaryOp(b) => list.push(Object::BinaryOp(b)),
-            Token::Integer(n) => list.push(Object::Integer(n)),
-            Token::Float(f) => list.push(Object::Float(f)),
-            Token::String(s) => list.push(Object::String(s)),
-            Token::Symbol(s) => list.push(Object::Symbol(s)),
             Token::LParen => {
-                tokens.push(Token::LParen);
+                tokens.push((Token::LParen, span));
                 let sub_list = parse_list(tokens)?;
                 list.push(sub_list);
             }
             Token::RParen => {
-                return Ok(Object::List(Rc::new(list)));
+                end = Some(span);
+                break;
             }
+            Token::DatumComment => {
+                parse_datum(tokens, &span)?;
+            }
+            Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing => {
+                list.push(parse_quoted(&t, &span, tokens)?);
+            }
+            Token::Symbol(s) if s == "." => {
+                if dot.is_some() || list.is_empty() {
+                    return Err(unexpected_dot(span));
+                }
+                dot = Some((list.len(), span));
+                list.push(Object::Symbol(s));
+            }
+            atom => list.push(parse_atom(atom)),
+        }
+    }
+    let end = end.ok_or_else(|| ParseError {
+        err: "Unclosed list".to_string(),
+        span: Some(start.clone()),
+    })?;
+    // In a dotted list `(a b . c)` exactly one datum follows the dot.
+    if let Some((i, span)) = dot {
+        if i + 2 != list.len() {
+            return Err(unexpected_dot(span));
+        }
+    }
+
+    let list = Rc::new(list);
+    record_span(&list, start.to(&end));
+    Ok(Object::List(list))
+}
+
+fn parse_atom(token: Token) -> Object {
+    match token {
+        Token::Integer(n) => Object::Integer(n),
+        Token::Float(f) => Object::Float(f),
+        Token::String(s) => Object::String(s),
+        Token::Symbol(s) => Object::Symbol(s),
+        Token::LParen
+        | Token::RParen
+        | Token::DatumComment
+        | Token::Quote
+        | Token::Quasiquote
+        | Token::Unquote
+        | Token::UnquoteSplicing => {
+            unreachable!("{:?} is not an atom", token)
+        }
+    }
+}
+
+fn unexpected_dot(span: Span) -> ParseError {
+    ParseError {
+        err: "Unexpected dot".to_string(),
+        span: Some(span),
+    }
+}
+
+/// Parses the next datum, an atom or a list. `prev` is the span of the token
+/// before it, used to report a missing datum.
+fn parse_datum(tokens: &mut Vec<(Token, Span)>, prev: &Span) -> Result<Object, ParseError> {
+    match tokens.pop() {
+        Some((Token::LParen, span)) => {
+            tokens.push((Token::LParen, span));
+            parse_list(tokens)
         }
+        Some((Token::DatumComment, span)) => {
+            parse_datum(tokens, &span)?;
+            parse_datum(tokens, &span)
+        }
+        Some((
+            quote @ (Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing),
+            span,
+        )) => parse_quoted(&quote, &span, tokens),
+        Some((Token::RParen, span)) => Err(ParseError {
+            err: "Expected a datum, found RParen".to_string(),
+            span: Some(span),
+        }),
+        Some((Token::Symbol(s), span)) if s == "." => Err(unexpected_dot(span)),
+        Some((atom, _)) => Ok(parse_atom(atom)),
+        None => Err(ParseError {
+            err: "Expected a datum, found end of input".to_string(),
+            span: Some(prev.clone()),
+        }),
     }
+}
 
-    Ok(Object::List(Rc::new(list)))
+/// Parses the datum following the quote token `quote` at `span`, expanding
+/// e.g. `'x` into `(quote x)`.
+fn parse_quoted(
+    quote: &Token,
+    span: &Span,
+    tokens: &mut Vec<(Token, Span)>,
+) -> Result<Object, ParseError> {
+    let name = match quote {
+        Token::Quote => "quote",
+        Token::Quasiquote => "quasiquote",
+        Token::Unquote => "unquote",
+        _ => "unquote-splicing",
+    };
+    let next = tokens.last().map(|(_, span)| span.clone());
+    let datum = parse_datum(tokens, span)?;
+    let end = match &datum {
+        Object::List(list) => span_of(list),
+        _ => next,
+    };
+    let list = Rc::new(vec![Object::Symbol(name.to_string()), datum]);
+    record_span(&list, span.to(end.as_ref().unwrap_or(span)));
+    Ok(Object::List(list))
 }
 
 #[cfg(test)]
@@ -74,15 +263,82 @@ mod tests {
 
     #[test]
     fn test_add() {
-        let list = parse("(+ 1 2)").unwrap();
+        let forms = parse("(+ 1 2)").unwrap();
         assert_eq!(
-            list,
-            Object::List(Rc::new(vec![
-                Object::BinaryOp("+".to_string()),
+            forms,
+            vec![Object::List(Rc::new(vec![
+                Object::Symbol("+".to_string()),
                 Object::Integer(1),
                 Object::Integer(2),
-            ]))
+            ]))]
+        );
+    }
+
+    #[test]
+    fn test_line_and_block_comments() {
+        let program = "
+            ; adds two numbers
+            (+ 1 #| the second
+                    #| nested |#
+                    operand |# 2) ; done
+        ";
+        assert_eq!(parse(program).unwrap(), parse("(+ 1 2)").unwrap());
+    }
+
+    #[test]
+    fn test_datum_comments() {
+        assert_eq!(
+            parse("(+ #;(* 2 3) 1 #;x 2)").unwrap(),
+            parse("(+ 1 2)").unwrap()
+        );
+        assert_eq!(
+            parse("(list #; #; a b c)").unwrap(),
+            parse("(list c)").unwrap()
+        );
+        assert_eq!(
+            parse("#;(ignored) (+ 1 2)").unwrap(),
+            parse("(+ 1 2)").unwrap()
         );
+        assert!(parse("(+ 1 #;)").is_err());
+        assert!(parse("(+ 1 #;").is_err());
+    }
+
+    #[test]
+    fn test_dotted_lists() {
+        assert!(parse("(a . b)").is_ok());
+        assert!(parse("(a b . (c))").is_ok());
+        for program in ["(. a)", "(a .)", "(a . b c)", "(a . . b)", ".", "'."] {
+            match parse(program) {
+                Err(LispError::Parse(err)) => assert_eq!(err.err, "Unexpected dot", "{}", program),
+                result => panic!("{}: unexpected {:?}", program, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_quote_shorthands() {
+        assert_eq!(
+            parse("(list 'a '(b c) `(d ,e ,@f) ''g)").unwrap(),
+            parse(
+                "(list (quote a) (quote (b c)) \
+                 (quasiquote (d (unquote e) (unquote-splicing f))) (quote (quote g)))"
+            )
+            .unwrap()
+        );
+        assert!(parse("(list ')").is_err());
+        assert!(parse("(list '").is_err());
+    }
+
+    #[test]
+    fn test_written_strings_round_trip() {
+        let program = r#"(print "tab\there" "quote \" and \\" "line\nbreak" "\x7;" 1.0 #t)"#;
+        let list = parse(program).unwrap().remove(0);
+        let written = list.written().to_string();
+        assert_eq!(
+            written,
+            r#"(print "tab\there" "quote \" and \\" "line\nbreak" "\x7;" 1.0 #t)"#
+        );
+        assert_eq!(parse(&written).unwrap(), vec![list]);
     }
 
     #[test]
@@ -92,30 +348,102 @@ mod tests {
                          (define pi 314)
                          (* pi (* r r))
                        )";
-        let list = parse(program).unwrap();
+        let forms = parse(program).unwrap();
         assert_eq!(
-            list,
-            Object::List(Rc::new(vec![
+            forms,
+            vec![Object::List(Rc::new(vec![
                 Object::List(Rc::new(vec![
-                    Object::Keyword("define".to_string()),
+                    Object::Symbol("define".to_string()),
                     Object::Symbol("r".to_string()),
                     Object::Integer(10),
                 ])),
                 Object::List(Rc::new(vec![
-                    Object::Keyword("define".to_string()),
+                    Object::Symbol("define".to_string()),
                     Object::Symbol("pi".to_string()),
                     Object::Integer(314),
                 ])),
                 Object::List(Rc::new(vec![
-                    Object::BinaryOp("*".to_string()),
+                    Object::Symbol("*".to_string()),
                     Object::Symbol("pi".to_string()),
                     Object::List(Rc::new(vec![
-                        Object::BinaryOp("*".to_string()),
+                        Object::Symbol("*".to_string()),
                         Object::Symbol("r".to_string()),
                         Object::Symbol("r".to_string()),
                     ])),
                 ])),
-            ]))
+            ]))]
+        );
+    }
+
+    #[test]
+    fn test_multiple_forms_and_atoms() {
+        let forms = parse("42 \"s\" x (f 1) 'y #;(skipped) ; done").unwrap();
+        assert_eq!(forms.len(), 5);
+        assert_eq!(
+            forms[..3],
+            [
+                Object::Integer(42),
+                Object::String("s".to_string()),
+                Object::Symbol("x".to_string()),
+            ]
+        );
+        assert_eq!(parse("").unwrap(), vec![]);
+        assert_eq!(parse("#;1").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_unbalanced_parens() {
+        let err = parse("(+ 1 2))").unwrap_err();
+        assert_eq!(err.span().map(|span| span.start), Some(7));
+        assert!(err.to_string().contains("Unexpected RParen"));
+        let err = parse("(define x (+ 1 2)").unwrap_err();
+        assert_eq!(err.span().map(|span| span.start), Some(0));
+        assert!(err.to_string().contains("Unclosed list"));
+    }
+
+    fn status(input: &str) -> ParseStatus {
+        parse_status(&Source::new("<input>", input))
+    }
+
+    #[test]
+    fn test_parse_status_complete() {
+        assert_eq!(
+            status("(+ 1 2) x"),
+            ParseStatus::Complete(parse("(+ 1 2) x").unwrap())
         );
+        assert_eq!(status(""), ParseStatus::Complete(vec![]));
+    }
+
+    #[test]
+    fn test_parse_status_incomplete() {
+        let incomplete = |open_parens, in_string| ParseStatus::Incomplete {
+            open_parens,
+            in_string,
+        };
+        assert_eq!(status("(define x"), incomplete(1, false));
+        assert_eq!(status("(define (f x)\n  (+ x"), incomplete(2, false));
+        assert_eq!(status("(print \"hello"), incomplete(1, true));
+        assert_eq!(status("(print \"hello\\"), incomplete(1, true));
+        assert_eq!(status("#r#\"raw"), incomplete(0, true));
+        assert_eq!(status("(+ 1 #| comment"), incomplete(1, false));
+        assert_eq!(status("'"), incomplete(0, false));
+        assert_eq!(status("(list 1 #;"), incomplete(1, false));
+    }
+
+    #[test]
+    fn test_parse_status_error() {
+        assert!(matches!(
+            status("(+ 1 2))"),
+            ParseStatus::Error(LispError::Parse(_))
+        ));
+        assert!(matches!(
+            status("(define x [1"),
+            ParseStatus::Error(LispError::Tokenize(_))
+        ));
+        assert!(matches!(
+            status(") ("),
+            ParseStatus::Error(LispError::Parse(_))
+        ));
+        assert!(matches!(status("(')"), ParseStatus::Error(_)));
     }
 }
@@ -0,0 +1,182 @@
+use crate::object::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+/// A named piece of program text, e.g. the contents of a script file.
+#[derive(Debug, PartialEq)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: &str, text: &str) -> Rc<Self> {
+        Rc::new(Source {
+            name: name.to_string(),
+            text: text.to_string(),
+        })
+    }
+}
+
+/// A region of a `Source`. `start` and `end` are byte offsets, `line` and
+/// `column` (both 1-based) locate `start`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Returns a span covering both `self` and `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end,
+            ..self.clone()
+        }
+    }
+}
+
+/// Renders the location followed by the source line with the span
+/// underlined. Spans covering several lines are underlined up to the end of
+/// their first line.
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = &self.source.text;
+        let line_start = text[..self.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[self.start..]
+            .find('\n')
+            .map_or(text.len(), |i| self.start + i);
+        let line = &text[line_start..line_end];
+        let indent = text[line_start..self.start].chars().count();
+        let width = text[self.start..self.end.clamp(self.start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter, self.source.name, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, line)?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(indent),
+            "^".repeat(width)
+        )
+    }
+}
+
+/// A side table of values about objects shared through `Rc`s, such as the
+/// spans of parsed lists. Entries hold a weak reference to their object, so
+/// that the table does not keep it alive, and are removed some time after it
+/// is dropped. Tables keyed by the address of their object must check that
+/// the object found is the one looked up, as the address of a dropped object
+/// can be reused by another.
+pub struct WeakTable<T, V> {
+    entries: HashMap<usize, (Weak<T>, V)>,
+    prune_at: usize,
+}
+
+const MIN_PRUNE_AT: usize = 1024;
+
+impl<T, V> WeakTable<T, V> {
+    pub fn new() -> Self {
+        WeakTable {
+            entries: HashMap::new(),
+            prune_at: MIN_PRUNE_AT,
+        }
+    }
+
+    /// Stores `value` under `key` for as long as `object` is alive.
+    pub fn insert(&mut self, key: usize, object: &Rc<T>, value: V) {
+        if self.entries.len() >= self.prune_at {
+            self.entries
+                .retain(|_, (object, _)| object.strong_count() > 0);
+            self.prune_at = (self.entries.len() * 2).max(MIN_PRUNE_AT);
+        }
+        self.entries.insert(key, (Rc::downgrade(object), value));
+    }
+
+    /// Returns the value stored under `key` and its object, unless the
+    /// object has been dropped.
+    pub fn get(&self, key: usize) -> Option<(Rc<T>, &V)> {
+        let (object, value) = self.entries.get(&key)?;
+        Some((object.upgrade()?, value))
+    }
+}
+
+impl<T, V> Default for WeakTable<T, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Parsed lists are shared through `Rc`s, so the spans of list forms are kept
+// in a side table keyed by the address of the list.
+thread_local! {
+    static SPANS: RefCell<WeakTable<Vec<Object>, Span>> = RefCell::new(WeakTable::new());
+}
+
+/// Remembers where in the source the list `list` was parsed from.
+pub fn record_span(list: &Rc<Vec<Object>>, span: Span) {
+    SPANS.with(|table| {
+        let key = Rc::as_ptr(list) as usize;
+        table.borrow_mut().insert(key, list, span);
+    })
+}
+
+/// Returns where in the source the list `list` was parsed from, if known.
+pub fn span_of(list: &Rc<Vec<Object>>) -> Option<Span> {
+    SPANS.with(|table| {
+        let table = table.borrow();
+        let (found, span) = table.get(Rc::as_ptr(list) as usize)?;
+        Rc::ptr_eq(&found, list).then(|| span.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_span() {
+        let source = Source::new("test.lisp", "(define x 1)\n(+ x \"a\")\n");
+        let span = Span {
+            source,
+            start: 13,
+            end: 22,
+            line: 2,
+            column: 1,
+        };
+        assert_eq!(
+            span.to_string(),
+            " --> test.lisp:2:1\n  |\n2 | (+ x \"a\")\n  | ^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_span_table() {
+        let source = Source::new("test.lisp", "(1)");
+        let span = Span {
+            source,
+            start: 0,
+            end: 3,
+            line: 1,
+            column: 1,
+        };
+        let list = Rc::new(vec![Object::Integer(1)]);
+        record_span(&list, span.clone());
+        assert_eq!(span_of(&list), Some(span));
+        assert_eq!(span_of(&Rc::new(vec![Object::Integer(1)])), None);
+    }
+}
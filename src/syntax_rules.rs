@@ -0,0 +1,562 @@
+use crate::env::*;
+use crate::error::*;
+use crate::object::*;
+use crate::span::WeakTable;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A pattern-based macro defined with `syntax-rules`.
+///
+/// Expansion is hygienic in the common case. Variables bound by the binding
+/// forms of a template, such as the temporaries of a `swap!` macro, are
+/// renamed in every expansion so they cannot capture the variables of the
+/// code using the macro. Other symbols introduced by a template, such as `if`
+/// or the global functions it calls, refer to their binding where the macro
+/// was defined, even if the code using the macro binds the same names.
+pub struct SyntaxRules {
+    id: usize,
+    ellipsis: String,
+    literals: Vec<String>,
+    rules: Vec<Rule>,
+    env: Rc<RefCell<Env>>,
+}
+
+/// A rule of a `syntax-rules` macro, with the variables bound by the binding
+/// forms of its template that do not come from the pattern.
+struct Rule {
+    pattern: Object,
+    template: Object,
+    binders: HashSet<String>,
+}
+
+/// What a pattern variable matched: a single form, or a sequence of matches
+/// for a variable followed by an ellipsis in the pattern.
+#[derive(Debug, Clone)]
+enum Binding {
+    One(Object),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+// Renamed variables look like `#:name#3`, and aliases for the symbols of a
+// macro definition `#:name@3`. The reader rejects symbols starting with
+// `#:`, so they cannot clash with the symbols of the source code.
+static EXPANSIONS: AtomicUsize = AtomicUsize::new(0);
+
+// The environments where the macros that made aliases were defined, by the
+// number of the macro.
+thread_local! {
+    static ALIAS_ENVS: RefCell<WeakTable<RefCell<Env>, ()>> = RefCell::new(WeakTable::new());
+}
+
+/// Splits an alias into the symbol it stands for and the number of the macro
+/// that made it.
+fn split_alias(name: &str) -> Option<(&str, usize)> {
+    let (symbol, id) = name.strip_prefix("#:")?.rsplit_once('@')?;
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((symbol, id.parse().ok()?))
+}
+
+/// Returns the symbol and the environment an alias made by a `syntax-rules`
+/// expansion refers to, or `None` if `name` is not an alias.
+pub fn resolve_alias(name: &str) -> Option<(String, Rc<RefCell<Env>>)> {
+    let (symbol, id) = split_alias(name)?;
+    let env = ALIAS_ENVS.with(|table| table.borrow().get(id).map(|(env, _)| env))?;
+    Some((symbol.to_string(), env))
+}
+
+/// Returns the symbol `name` stands for if it is an alias, and `name`
+/// otherwise. Special forms use it to recognize keywords such as `else`.
+pub fn source_name(name: &str) -> &str {
+    split_alias(name).map_or(name, |(symbol, _)| symbol)
+}
+
+impl SyntaxRules {
+    /// Builds a macro from the form `(syntax-rules (literals...) rules...)`,
+    /// or `(syntax-rules ellipsis (literals...) rules...)` to use another
+    /// symbol than `...` as the ellipsis. `env` is where the macro is
+    /// defined.
+    pub fn new(form: &[Object], env: &Rc<RefCell<Env>>) -> Result<Self, LispError> {
+        let invalid =
+            |message: &str| LispError::syntax(message, &Object::List(Rc::new(form.to_vec())));
+        let (ellipsis, rest) = match form.get(1) {
+            Some(Object::Symbol(s)) => (s.clone(), &form[2..]),
+            _ => ("...".to_string(), &form[1..]),
+        };
+        let literals = match rest.first() {
+            Some(Object::List(literals)) => literals
+                .iter()
+                .map(|literal| match literal {
+                    Object::Symbol(s) => Ok(s.clone()),
+                    _ => Err(invalid("Invalid literal in syntax-rules")),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(invalid("Expected a list of literals in syntax-rules")),
+        };
+        let mut macro_rules = SyntaxRules {
+            id: EXPANSIONS.fetch_add(1, Ordering::Relaxed),
+            ellipsis,
+            literals,
+            rules: Vec::new(),
+            env: env.clone(),
+        };
+        for rule in rest[1..].iter() {
+            match rule {
+                Object::List(rule) if rule.len() == 2 && matches!(rule[0], Object::List(_)) => {
+                    let vars = macro_rules.pattern_vars(&rule[0]);
+                    let mut binders = HashSet::new();
+                    macro_rules.template_binders(&rule[1], &vars, &mut binders);
+                    macro_rules.rules.push(Rule {
+                        pattern: rule[0].clone(),
+                        template: rule[1].clone(),
+                        binders,
+                    });
+                }
+                _ => return Err(invalid("Invalid rule in syntax-rules")),
+            }
+        }
+        Ok(macro_rules)
+    }
+
+    /// Expands the macro call `form`, made in `env`, with the first rule
+    /// whose pattern matches it.
+    pub fn expand(&self, form: &[Object], env: &Rc<RefCell<Env>>) -> Result<Object, LispError> {
+        for rule in self.rules.iter() {
+            let pattern = match &rule.pattern {
+                Object::List(pattern) => pattern,
+                _ => continue,
+            };
+            let mut bindings = Bindings::new();
+            // The keyword position of the pattern is ignored.
+            if !pattern.is_empty() && self.match_list(&pattern[1..], &form[1..], &mut bindings) {
+                let mut expansion = Expansion {
+                    rules: self,
+                    binders: &rule.binders,
+                    use_env: env,
+                    id: EXPANSIONS.fetch_add(1, Ordering::Relaxed),
+                    renames: HashMap::new(),
+                    quoted: false,
+                    quasi: 0,
+                };
+                return expansion.transcribe(&rule.template, &bindings, true);
+            }
+        }
+        Err(LispError::syntax(
+            "No syntax-rules pattern matches",
+            &Object::List(Rc::new(form.to_vec())),
+        ))
+    }
+
+    fn is_ellipsis(&self, obj: &Object) -> bool {
+        matches!(obj, Object::Symbol(s) if *s == self.ellipsis)
+    }
+
+    fn match_pattern(&self, pattern: &Object, form: &Object, bindings: &mut Bindings) -> bool {
+        match pattern {
+            Object::Symbol(s) if s == "_" => true,
+            Object::Symbol(s) if self.literals.contains(s) => {
+                matches!(form, Object::Symbol(f) if source_name(f) == s)
+            }
+            Object::Symbol(s) => {
+                bindings.insert(s.clone(), Binding::One(form.clone()));
+                true
+            }
+            Object::List(patterns) => match form {
+                Object::List(forms) => self.match_list(patterns, forms, bindings),
+                _ => false,
+            },
+            _ => pattern == form,
+        }
+    }
+
+    fn match_list(&self, patterns: &[Object], forms: &[Object], bindings: &mut Bindings) -> bool {
+        if let [patterns @ .., Object::Symbol(dot), tail] = patterns {
+            if dot == "." {
+                return self.match_dotted(patterns, tail, forms, bindings);
+            }
+        }
+        let ellipsis = match patterns.iter().position(|p| self.is_ellipsis(p)) {
+            Some(i) if i > 0 => i,
+            _ => {
+                return patterns.len() == forms.len()
+                    && patterns
+                        .iter()
+                        .zip(forms)
+                        .all(|(p, f)| self.match_pattern(p, f, bindings))
+            }
+        };
+        let (before, repeated, after) = (
+            &patterns[..ellipsis - 1],
+            &patterns[ellipsis - 1],
+            &patterns[ellipsis + 1..],
+        );
+        if forms.len() < before.len() + after.len() {
+            return false;
+        }
+        let repeats = forms.len() - before.len() - after.len();
+        if !self.match_list(before, &forms[..before.len()], bindings)
+            || !self.match_list(after, &forms[before.len() + repeats..], bindings)
+        {
+            return false;
+        }
+
+        let mut matches = Vec::new();
+        for form in forms[before.len()..before.len() + repeats].iter() {
+            let mut sub_bindings = Bindings::new();
+            if !self.match_pattern(repeated, form, &mut sub_bindings) {
+                return false;
+            }
+            matches.push(sub_bindings);
+        }
+        for var in self.pattern_vars(repeated) {
+            let sequence = matches.iter_mut().filter_map(|m| m.remove(&var)).collect();
+            bindings.insert(var, Binding::Many(sequence));
+        }
+        true
+    }
+
+    /// Matches the pattern `(patterns... . tail)`: `tail` matches the list of
+    /// the forms left after the ones matching `patterns`, or the tail of a
+    /// dotted form.
+    fn match_dotted(
+        &self,
+        patterns: &[Object],
+        tail: &Object,
+        forms: &[Object],
+        bindings: &mut Bindings,
+    ) -> bool {
+        let (forms, form_tail) = match forms {
+            [items @ .., Object::Symbol(dot), form_tail] if dot == "." => (items, Some(form_tail)),
+            _ => (forms, None),
+        };
+        // Patterns followed by an ellipsis take all the forms they can.
+        let count = if patterns.iter().any(|p| self.is_ellipsis(p)) {
+            forms.len()
+        } else {
+            patterns.len()
+        };
+        if forms.len() < count || !self.match_list(patterns, &forms[..count], bindings) {
+            return false;
+        }
+        let rest = match (&forms[count..], form_tail) {
+            ([], Some(form_tail)) => form_tail.clone(),
+            (rest, form_tail) => {
+                let mut rest = rest.to_vec();
+                if let Some(form_tail) = form_tail {
+                    rest.extend([Object::Symbol(".".to_string()), form_tail.clone()]);
+                }
+                Object::List(Rc::new(rest))
+            }
+        };
+        self.match_pattern(tail, &rest, bindings)
+    }
+
+    fn pattern_vars(&self, pattern: &Object) -> Vec<String> {
+        match pattern {
+            Object::Symbol(s)
+                if s == "_" || s == "." || self.literals.contains(s) || *s == self.ellipsis =>
+            {
+                vec![]
+            }
+            Object::Symbol(s) => vec![s.clone()],
+            Object::List(patterns) => patterns.iter().flat_map(|p| self.pattern_vars(p)).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Collects into `binders` the symbols of `template` bound by `let`,
+    /// `let*`, `letrec`, `letrec*`, `lambda`, `define` and `do` forms, other
+    /// than the pattern variables `vars`.
+    fn template_binders(&self, template: &Object, vars: &[String], binders: &mut HashSet<String>) {
+        let items = match template {
+            Object::List(items) => items,
+            _ => return,
+        };
+        let mut bound = Vec::new();
+        match items.first() {
+            Some(Object::Symbol(s)) if s == "quote" => return,
+            Some(Object::Symbol(s)) if s == "lambda" => {
+                if let Some(params) = items.get(1) {
+                    param_names(params, &mut bound);
+                }
+            }
+            Some(Object::Symbol(s)) if s == "define" => match items.get(1) {
+                Some(Object::List(signature)) => signature
+                    .iter()
+                    .for_each(|param| param_names(param, &mut bound)),
+                Some(name) => bound.push(name),
+                None => {}
+            },
+            Some(Object::Symbol(s))
+                if ["let", "let*", "letrec", "letrec*", "do"].contains(&s.as_str()) =>
+            {
+                let bindings = match items.get(1) {
+                    Some(name @ Object::Symbol(_)) => {
+                        bound.push(name);
+                        items.get(2)
+                    }
+                    bindings => bindings,
+                };
+                if let Some(Object::List(bindings)) = bindings {
+                    for binding in bindings.iter() {
+                        if let Object::List(binding) = binding {
+                            bound.extend(binding.first());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        for name in bound {
+            match name {
+                Object::Symbol(s)
+                    if !vars.contains(s) && s != "." && s != "_" && *s != self.ellipsis =>
+                {
+                    binders.insert(s.clone());
+                }
+                _ => {}
+            }
+        }
+        for item in items.iter() {
+            self.template_binders(item, vars, binders);
+        }
+    }
+}
+
+/// Collects the names in the parameter list `params`, or the parameter
+/// `params` itself, including the names of optional parameters.
+fn param_names<'a>(params: &'a Object, names: &mut Vec<&'a Object>) {
+    match params {
+        Object::List(params) => {
+            for param in params.iter() {
+                match param {
+                    Object::List(optional) => names.extend(optional.first()),
+                    param => names.push(param),
+                }
+            }
+        }
+        param => names.push(param),
+    }
+}
+
+/// The state of one expansion: the names given to the variables bound by the
+/// template, whether the template being transcribed is quoted, and how many
+/// quasiquotes it is nested in, not counting the unquoted ones.
+struct Expansion<'a> {
+    rules: &'a SyntaxRules,
+    binders: &'a HashSet<String>,
+    use_env: &'a Rc<RefCell<Env>>,
+    id: usize,
+    renames: HashMap<String, String>,
+    quoted: bool,
+    quasi: usize,
+}
+
+impl Expansion<'_> {
+    /// Instantiates `template` with `bindings`. Ellipses in the template are
+    /// only special if `ellipsis` is set, which `(... template)` turns off.
+    fn transcribe(
+        &mut self,
+        template: &Object,
+        bindings: &Bindings,
+        ellipsis: bool,
+    ) -> Result<Object, LispError> {
+        match template {
+            Object::Symbol(s) => match bindings.get(s) {
+                Some(Binding::One(form)) => Ok(form.clone()),
+                Some(Binding::Many(_)) => Err(LispError::syntax(
+                    "Pattern variable used without an ellipsis",
+                    template,
+                )),
+                None => Ok(Object::Symbol(self.rename(s))),
+            },
+            Object::List(templates) => {
+                match templates.as_slice() {
+                    [head, template] if ellipsis && self.rules.is_ellipsis(head) => {
+                        return self.transcribe(template, bindings, false)
+                    }
+                    // Quoted symbols are data, so they are not renamed.
+                    [Object::Symbol(quote), _]
+                        if quote == "quote" && !self.quoted && self.quasi == 0 =>
+                    {
+                        self.quoted = true;
+                        let datum = self.transcribe(template, bindings, ellipsis);
+                        self.quoted = false;
+                        return datum;
+                    }
+                    // So are quasiquoted symbols, except in unquoted forms.
+                    [Object::Symbol(head), template]
+                        if !self.quoted
+                            && (head == "quasiquote"
+                                || self.quasi > 0
+                                    && (head == "unquote" || head == "unquote-splicing")) =>
+                    {
+                        let quasi = self.quasi;
+                        self.quasi = if head == "quasiquote" {
+                            quasi + 1
+                        } else {
+                            quasi - 1
+                        };
+                        let arg = self.transcribe(template, bindings, ellipsis);
+                        self.quasi = quasi;
+                        return Ok(Object::List(Rc::new(vec![
+                            Object::Symbol(head.clone()),
+                            arg?,
+                        ])));
+                    }
+                    _ => {}
+                }
+                let mut items = Vec::new();
+                let mut i = 0;
+                while i < templates.len() {
+                    let mut depth = 0;
+                    while ellipsis
+                        && templates
+                            .get(i + depth + 1)
+                            .is_some_and(|t| self.rules.is_ellipsis(t))
+                    {
+                        depth += 1;
+                    }
+                    if depth == 0 {
+                        items.push(self.transcribe(&templates[i], bindings, ellipsis)?);
+                    } else {
+                        self.transcribe_repeated(&templates[i], depth, bindings, &mut items)?;
+                    }
+                    i += depth + 1;
+                }
+                // A template `(f . args)` where `args` is a list is the list
+                // `(f args...)`.
+                if let [.., Object::Symbol(dot), Object::List(_)] = items.as_slice() {
+                    if dot == "." {
+                        if let Some(Object::List(tail)) = items.pop() {
+                            items.pop();
+                            items.extend(tail.iter().cloned());
+                        }
+                    }
+                }
+                Ok(Object::List(Rc::new(items)))
+            }
+            _ => Ok(template.clone()),
+        }
+    }
+
+    /// Instantiates `template` followed by `depth` ellipses once for every
+    /// match of the pattern variables it contains.
+    fn transcribe_repeated(
+        &mut self,
+        template: &Object,
+        depth: usize,
+        bindings: &Bindings,
+        items: &mut Vec<Object>,
+    ) -> Result<(), LispError> {
+        if depth == 0 {
+            items.push(self.transcribe(template, bindings, true)?);
+            return Ok(());
+        }
+        let vars = symbols(template)
+            .into_iter()
+            .filter(|s| matches!(bindings.get(s), Some(Binding::Many(_))))
+            .collect::<Vec<_>>();
+        let lengths = vars.iter().map(|var| match &bindings[var] {
+            Binding::Many(matches) => matches.len(),
+            Binding::One(_) => 0,
+        });
+        let repeats = match lengths.clone().min() {
+            Some(n) if lengths.clone().all(|len| len == n) => n,
+            Some(_) => {
+                return Err(LispError::syntax(
+                    "Pattern variables under an ellipsis matched different numbers of forms",
+                    template,
+                ))
+            }
+            None => {
+                return Err(LispError::syntax(
+                    "No pattern variable to repeat before an ellipsis",
+                    template,
+                ))
+            }
+        };
+        for i in 0..repeats {
+            let mut iteration = bindings.clone();
+            for var in vars.iter() {
+                if let Binding::Many(matches) = &bindings[var] {
+                    iteration.insert(var.clone(), matches[i].clone());
+                }
+            }
+            self.transcribe_repeated(template, depth - 1, &iteration, items)?;
+        }
+        Ok(())
+    }
+
+    /// Names the symbol `name` introduced by the template. Variables bound
+    /// by the template are renamed. Other symbols are kept if they refer to
+    /// the same binding where the macro is used as where it was defined, and
+    /// replaced by an alias for the binding of the definition otherwise.
+    fn rename(&mut self, name: &str) -> String {
+        if self.quoted || self.quasi > 0 || name.starts_with('#') || name == "." {
+            return name.to_string();
+        }
+        if self.binders.contains(name) {
+            let id = self.id;
+            return self
+                .renames
+                .entry(name.to_string())
+                .or_insert_with(|| format!("#:{}#{}", name, id))
+                .clone();
+        }
+        let same_binding = match (
+            Env::scope_of(&self.rules.env, name),
+            Env::scope_of(self.use_env, name),
+        ) {
+            (Some(defined), Some(used)) => Rc::ptr_eq(&defined, &used),
+            (defined, used) => defined.is_none() && used.is_none(),
+        };
+        if same_binding {
+            return name.to_string();
+        }
+        ALIAS_ENVS.with(|table| {
+            let mut table = table.borrow_mut();
+            if table.get(self.rules.id).is_none() {
+                table.insert(self.rules.id, &self.rules.env, ());
+            }
+        });
+        format!("#:{}@{}", name, self.rules.id)
+    }
+}
+
+fn symbols(obj: &Object) -> Vec<String> {
+    match obj {
+        Object::Symbol(s) => vec![s.clone()],
+        Object::List(list) => list.iter().flat_map(symbols).collect(),
+        _ => vec![],
+    }
+}
+
+impl fmt::Debug for SyntaxRules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SyntaxRules")
+            .field("ellipsis", &self.ellipsis)
+            .field("literals", &self.literals)
+            .field(
+                "rules",
+                &self
+                    .rules
+                    .iter()
+                    .map(|rule| (&rule.pattern, &rule.template))
+                    .collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for SyntaxRules {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}